@@ -0,0 +1,111 @@
+#![no_main]
+
+use arbitrary::Arbitrary;
+use blox::ast::Expression;
+use blox::interpreter::{Interpreter, Value};
+use blox::jit::JIT;
+use blox::lexer::TokenKind;
+use blox::optimizer::fold;
+use libfuzzer_sys::fuzz_target;
+
+// Expressions built straight from fuzzer bytes, so every input is a valid
+// tree instead of most of them being parse errors. Strings, booleans and
+// comparisons are in here too so the JIT's type checks get exercised, not
+// just its arithmetic.
+#[derive(Arbitrary, Debug)]
+enum Expr {
+    Number(f64),
+    String(String),
+    Bool(bool),
+    Nil,
+    Negate(Box<Expr>),
+    Not(Box<Expr>),
+    Grouping(Box<Expr>),
+    Binary(Box<Expr>, Operator, Box<Expr>),
+}
+
+#[derive(Arbitrary, Debug, Clone, Copy)]
+enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Less,
+    Equal,
+}
+
+// deep enough to be interesting without overflowing the stack of the
+// recursive passes
+const MAX_DEPTH: usize = 64;
+
+impl Expr {
+    fn to_ast(&self, depth: usize) -> Expression {
+        if depth == MAX_DEPTH {
+            return Expression::Number(1.0);
+        }
+        let child = |expr: &Expr| Box::new(expr.to_ast(depth + 1));
+        match self {
+            Expr::Number(num) => Expression::Number(*num),
+            Expr::String(s) => Expression::String(s.clone()),
+            Expr::Bool(b) => Expression::Bool(*b),
+            Expr::Nil => Expression::Nil,
+            Expr::Negate(expr) => Expression::Unary(TokenKind::Minus, child(expr)),
+            Expr::Not(expr) => Expression::Unary(TokenKind::Bang, child(expr)),
+            Expr::Grouping(expr) => Expression::Grouping(child(expr)),
+            Expr::Binary(left, operator, right) => {
+                let operator = match operator {
+                    Operator::Add => TokenKind::Plus,
+                    Operator::Subtract => TokenKind::Minus,
+                    Operator::Multiply => TokenKind::Star,
+                    Operator::Divide => TokenKind::Slash,
+                    Operator::Less => TokenKind::Less,
+                    Operator::Equal => TokenKind::IsEqual,
+                };
+                Expression::Binary(child(left), operator, child(right))
+            }
+        }
+    }
+}
+
+// NaNs compare equal to each other here, every other number has to match
+// to the bit, so 0 and -0 are told apart
+fn same(left: f64, right: f64) -> bool {
+    (left.is_nan() && right.is_nan()) || left.to_bits() == right.to_bits()
+}
+
+fuzz_target!(|expr: Expr| {
+    let expression = expr.to_ast(0);
+
+    // the tree-walking interpreter is the reference
+    let expected = Interpreter::new().evaluate(&expression);
+    let folded = Interpreter::new().evaluate(&fold(expression.clone()));
+    let unchanged = match (&folded, &expected) {
+        (Ok(Value::Number(left)), Ok(Value::Number(right))) => same(*left, *right),
+        _ => folded == expected,
+    };
+    assert!(
+        unchanged,
+        "constant folding changed {:?} from {:?} to {:?}",
+        expression, expected, folded
+    );
+
+    // anything not provably a number stays in the interpreter
+    let mut jit = JIT::default();
+    if let Ok(code) = jit.compile_expression(expression.clone()) {
+        match expected {
+            Ok(Value::Number(num)) => assert!(
+                same(code(), num),
+                "JIT gave {} instead of {} for {:?}",
+                code(),
+                num,
+                expression
+            ),
+            other => panic!(
+                "JIT compiled {:?}, which evaluates to {:?}",
+                expression, other
+            ),
+        }
+    }
+    // otherwise every run maps more executable memory
+    unsafe { jit.free_memory() }
+});
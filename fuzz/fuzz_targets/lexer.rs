@@ -0,0 +1,41 @@
+#![no_main]
+
+use blox::lexer::{Lexer, LexerOptions, TokenKind};
+use libfuzzer_sys::fuzz_target;
+
+// Every token has to point at the text it came from, in order. Lexemes
+// borrow from the source, inside the span, which can be wider than the
+// lexeme: a string's span covers its quotes.
+fn check(src: &str, options: LexerOptions) {
+    let mut end = 0;
+    for token in Lexer::with_options(src, options) {
+        let span = token.span;
+        assert!(span.start >= end && span.start <= span.end, "{:?}", token);
+        let text = src
+            .get(span.start..span.end)
+            .unwrap_or_else(|| panic!("span off a char boundary {:?}", token));
+        if token.kind != TokenKind::Error {
+            let offset = (token.lexeme.as_ptr() as usize).wrapping_sub(src.as_ptr() as usize);
+            assert!(
+                span.start <= offset && offset + token.lexeme.len() <= span.end,
+                "lexeme outside of {:?} {:?}",
+                text,
+                token
+            );
+        }
+        end = span.end;
+    }
+}
+
+fuzz_target!(|input: (bool, bool, bool, &str)| {
+    let (unicode_identifiers, extended_numbers, string_interpolation, src) = input;
+    check(src, LexerOptions::default());
+    check(
+        src,
+        LexerOptions {
+            unicode_identifiers,
+            extended_numbers,
+            string_interpolation,
+        },
+    );
+});
@@ -0,0 +1,27 @@
+#![no_main]
+
+use blox::parser::Parser;
+use blox::resolver::Resolver;
+use libfuzzer_sys::fuzz_target;
+
+fn print(program: &[blox::ast::Statement]) -> String {
+    let statements: Vec<String> = program.iter().map(|s| s.to_string()).collect();
+    statements.join("\n")
+}
+
+fuzz_target!(|src: &str| {
+    let _ = Parser::new(src).parse();
+    let mut program = match Parser::new(src).parse_program() {
+        Ok(program) => program,
+        Err(_) => return,
+    };
+
+    // whatever parses has to print as source that parses to the same tree
+    let printed = print(&program);
+    match Parser::new(&printed).parse_program() {
+        Ok(reparsed) => assert_eq!(print(&reparsed), printed),
+        Err(e) => panic!("printed program doesn't parse: {:?}\n{}", e, printed),
+    }
+
+    let _ = Resolver::default().resolve(&mut program);
+});
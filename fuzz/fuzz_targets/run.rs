@@ -0,0 +1,33 @@
+#![no_main]
+
+use blox::interpreter::Interpreter;
+use blox::jit::JIT;
+use blox::limits::Limits;
+use blox::optimizer::optimize;
+use blox::parser::Parser;
+use blox::resolver::Resolver;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|src: &str| {
+    let mut program = match Parser::new(src).parse_program() {
+        Ok(program) => program,
+        Err(_) => return,
+    };
+    if Resolver::default().resolve(&mut program).is_err() {
+        return;
+    }
+    // the limits keep loops, recursion and string doubling from hanging or
+    // exhausting the fuzzer
+    let limits = Limits {
+        fuel: Some(10_000),
+        max_allocated: Some(1 << 20),
+        max_call_depth: Some(64),
+        ..Limits::default()
+    };
+    let _ = Interpreter::with_limits(limits).execute(&optimize(program));
+
+    // the JIT takes single expressions
+    let mut jit = JIT::default();
+    let _ = jit.compile(src);
+    unsafe { jit.free_memory() }
+});
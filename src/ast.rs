@@ -1,14 +1,106 @@
-use crate::lexer::TokenKind;
+use std::rc::Rc;
 
-pub type Identifier = String;
+use crate::lexer::{Token, TokenKind};
+use crate::object::InlineCache;
+use crate::source::Span;
+use crate::symbol::Symbol;
+use crate::tier::TieredFunction;
+
+pub type Identifier = Symbol;
+
+// Where a local lives once the resolver has run: how many scopes up from the
+// use site and its index inside that scope
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Local {
+    pub depth: usize,
+    pub slot: usize,
+}
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Variable {
+    pub name: Identifier,
+    pub line: u64,
+    // where the name was written, empty for nodes built outside the parser
+    pub span: Span,
+    // None until resolved, and stays None for globals
+    pub local: Option<Local>,
+}
+
+impl Variable {
+    pub fn new(name: Identifier, line: u64) -> Variable {
+        Variable {
+            name,
+            line,
+            span: Span::default(),
+            local: None,
+        }
+    }
+
+    // a variable named by the given token
+    pub fn at(name: Identifier, token: &Token<'_>) -> Variable {
+        Variable {
+            span: token.span,
+            ..Variable::new(name, token.line)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Expression {
     Number(f64),
+    String(String),
     Bool(bool),
     Nil,
-    Var(Identifier, Box<Expression>),
+    Variable(Variable),
+    This(Variable),
     Binary(Box<Expression>, TokenKind, Box<Expression>),
     Grouping(Box<Expression>),
     Unary(TokenKind, Box<Expression>),
+    Call(Box<Expression>, Vec<Expression>),
+    // property access on an instance, `object.name`, with the cache for
+    // this access site
+    Get(
+        Box<Expression>,
+        Identifier,
+        #[cfg_attr(feature = "serde", serde(skip))] InlineCache,
+    ),
+    Set(
+        Box<Expression>,
+        Identifier,
+        Box<Expression>,
+        #[cfg_attr(feature = "serde", serde(skip))] InlineCache,
+    ),
+    Assign(Variable, Box<Expression>),
+    // the value as print would show it, what string interpolation wraps
+    // every interpolated expression in
+    Stringify(Box<Expression>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Function {
+    pub name: Variable,
+    pub params: Vec<Variable>,
+    pub body: Vec<Statement>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub tier: TieredFunction,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Statement {
+    Expression(Expression),
+    Print(Expression),
+    Var(Variable, Option<Expression>),
+    Block(Vec<Statement>),
+    If(Expression, Box<Statement>, Option<Box<Statement>>),
+    While(Expression, Box<Statement>),
+    // declarations are shared with the closures made from them
+    Function(Rc<Function>),
+    // the line is kept for diagnostics about misplaced returns
+    Return(u64, Option<Expression>),
+    Class(Variable, Vec<Rc<Function>>),
 }
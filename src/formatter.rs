@@ -0,0 +1,251 @@
+use crate::lexer::{Lexer, Token, TokenKind};
+use crate::parser::Parser;
+
+// Source formatter behind `blox fmt`. It works on the token stream rather
+// than the AST, since the AST has already lost comments, for loops and
+// interpolated strings. Tokens are reprinted exactly as written, only the
+// whitespace between them changes: one statement per line, four space
+// indents, single spaces around binary operators and at most one blank line
+// in a row.
+
+const INDENT: &str = "    ";
+
+enum Piece<'src> {
+    Token(Token<'src>),
+    // a `//` or `/* */` comment, the lexer skips these
+    Comment(&'src str),
+}
+
+// a piece together with the number of line breaks in front of it
+type Spaced<'src> = (usize, Piece<'src>);
+
+// Comments in the whitespace between two tokens, with the line breaks
+// before each. Returns the line breaks left over after the last comment.
+fn comments<'src>(gap: &'src str, pieces: &mut Vec<Spaced<'src>>) -> usize {
+    let mut newlines = 0;
+    let mut rest = gap;
+    while let Some(c) = rest.chars().next() {
+        if rest.starts_with("//") {
+            let end = rest.find('\n').unwrap_or(rest.len());
+            pieces.push((newlines, Piece::Comment(rest[..end].trim_end())));
+            newlines = 0;
+            rest = &rest[end..];
+        } else if rest.starts_with("/*") {
+            let end = block_comment_end(rest);
+            pieces.push((newlines, Piece::Comment(&rest[..end])));
+            newlines = 0;
+            rest = &rest[end..];
+        } else {
+            if c == '\n' {
+                newlines += 1;
+            }
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    newlines
+}
+
+// byte length of the nested block comment rest starts with
+fn block_comment_end(rest: &str) -> usize {
+    let mut depth = 0;
+    let mut idx = 0;
+    while idx < rest.len() {
+        if rest[idx..].starts_with("/*") {
+            depth += 1;
+            idx += 2;
+        } else if rest[idx..].starts_with("*/") {
+            depth -= 1;
+            idx += 2;
+            if depth == 0 {
+                return idx;
+            }
+        } else {
+            idx += rest[idx..].chars().next().map_or(1, char::len_utf8);
+        }
+    }
+    rest.len()
+}
+
+fn lex(src: &str) -> Result<Vec<Spaced<'_>>, String> {
+    let mut pieces = Vec::new();
+    let mut end = 0;
+    for token in Lexer::new(src) {
+        if token.kind == TokenKind::Error {
+            return Err(format!("[line {}] Error: {}", token.line, token.lexeme));
+        }
+        let newlines = comments(&src[end..token.span.start], &mut pieces);
+        end = token.span.end;
+        pieces.push((newlines, Piece::Token(token)));
+    }
+    comments(&src[end..], &mut pieces);
+    Ok(pieces)
+}
+
+// whether a token can be the last token of an operand, which decides if a
+// following `-` is binary and a following `(` is a call
+fn ends_operand(kind: &TokenKind) -> bool {
+    matches!(
+        kind,
+        TokenKind::Ident(_)
+            | TokenKind::Number(_)
+            | TokenKind::String
+            | TokenKind::True
+            | TokenKind::False
+            | TokenKind::Nil
+            | TokenKind::This
+            | TokenKind::Super
+            | TokenKind::Rpar
+    )
+}
+
+struct Formatter<'src> {
+    src: &'src str,
+    out: String,
+    depth: usize,
+    // `;` inside parentheses separates for clauses instead of ending a line
+    parens: usize,
+    // the next piece has to start on a new line
+    line_break: bool,
+    previous: Option<TokenKind>,
+    // the previous token was a prefix `-` or `!`
+    previous_unary: bool,
+}
+
+impl<'src> Formatter<'src> {
+    fn new_line(&mut self, blank: bool) {
+        if !self.out.is_empty() {
+            self.out.push('\n');
+            if blank {
+                self.out.push('\n');
+            }
+        }
+        for _ in 0..self.depth {
+            self.out.push_str(INDENT);
+        }
+        self.line_break = false;
+    }
+
+    fn space_before(&self, token: &Token<'src>) -> bool {
+        let previous = match &self.previous {
+            Some(previous) => previous,
+            None => return false,
+        };
+        // the `}` that resumes a string after an interpolation
+        let continues_string = self.src[token.span.start..].starts_with('}')
+            && matches!(token.kind, TokenKind::String | TokenKind::Interpolation);
+
+        match (previous, &token.kind) {
+            (_, TokenKind::Rpar | TokenKind::Comma | TokenKind::Semicolon | TokenKind::Dot) => {
+                false
+            }
+            (TokenKind::LPar | TokenKind::Dot | TokenKind::Interpolation, _) => false,
+            _ if continues_string || self.previous_unary => false,
+            (previous, TokenKind::LPar) => !ends_operand(previous),
+            (TokenKind::LBrace, TokenKind::RBrace) => false,
+            _ => true,
+        }
+    }
+
+    fn token(&mut self, newlines: usize, token: Token<'src>) {
+        match token.kind {
+            TokenKind::RBrace => {
+                self.depth = self.depth.saturating_sub(1);
+                // empty blocks stay on one line
+                self.line_break = self.previous != Some(TokenKind::LBrace);
+            }
+            TokenKind::Else if self.previous == Some(TokenKind::RBrace) => self.line_break = false,
+            _ => {}
+        }
+
+        if self.line_break || self.out.is_empty() {
+            let blank = newlines > 1
+                && self.previous != Some(TokenKind::LBrace)
+                && token.kind != TokenKind::RBrace;
+            self.new_line(blank);
+        } else if self.space_before(&token) {
+            self.out.push(' ');
+        }
+        self.out
+            .push_str(&self.src[token.span.start..token.span.end]);
+
+        self.previous_unary = match token.kind {
+            TokenKind::Bang => true,
+            TokenKind::Minus => !self.previous.as_ref().is_some_and(ends_operand),
+            _ => false,
+        };
+        match token.kind {
+            TokenKind::LPar => self.parens += 1,
+            TokenKind::Rpar => self.parens = self.parens.saturating_sub(1),
+            TokenKind::LBrace => {
+                self.depth += 1;
+                self.line_break = true;
+            }
+            TokenKind::RBrace | TokenKind::DocComment => self.line_break = true,
+            TokenKind::Semicolon if self.parens == 0 => self.line_break = true,
+            _ => {}
+        }
+        self.previous = Some(token.kind);
+    }
+
+    // next_newlines is the number of line breaks after the comment, which
+    // decides if a block comment shares its line with what follows
+    fn comment(&mut self, newlines: usize, text: &str, next_newlines: usize) {
+        if newlines == 0 && !self.out.is_empty() {
+            // trailing comment, stays on the line it was written on
+            self.out.push(' ');
+        } else {
+            let blank = newlines > 1 && self.previous != Some(TokenKind::LBrace);
+            self.new_line(blank);
+        }
+        self.out.push_str(text);
+
+        if text.starts_with("//") || next_newlines > 0 {
+            self.line_break = true;
+        }
+    }
+}
+
+fn lexemes(src: &str) -> Vec<&str> {
+    Lexer::new(src).map(|token| token.lexeme).collect()
+}
+
+pub fn format(src: &str) -> Result<String, String> {
+    let pieces = lex(src)?;
+    // refuse to touch anything that doesn't parse
+    Parser::new(src)
+        .parse_program()
+        .map_err(|e| format!("{:?}", e))?;
+
+    let mut formatter = Formatter {
+        src,
+        out: String::new(),
+        depth: 0,
+        parens: 0,
+        line_break: false,
+        previous: None,
+        previous_unary: false,
+    };
+
+    let mut pieces = pieces.into_iter().peekable();
+    while let Some((newlines, piece)) = pieces.next() {
+        match piece {
+            Piece::Token(token) => formatter.token(newlines, token),
+            Piece::Comment(text) => {
+                let next_newlines = pieces.peek().map_or(1, |(newlines, _)| *newlines);
+                formatter.comment(newlines, text, next_newlines)
+            }
+        }
+    }
+
+    let mut out = formatter.out;
+    if !out.is_empty() {
+        out.push('\n');
+    }
+
+    // formatting only ever changes whitespace, make sure of that before
+    // anyone's file gets overwritten
+    if lexemes(src) != lexemes(&out) {
+        return Err("formatting would change the program, this is a bug in blox fmt".to_string());
+    }
+    Ok(out)
+}
@@ -0,0 +1,110 @@
+use crate::ast::{Expression, Local, Statement};
+use crate::lexer::TokenKind;
+
+// Static type of an expression as far as it can be proven without running it.
+// Dynamic covers everything that could hold more than one kind of value at
+// runtime, like globals which may be reassigned between calls.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Type {
+    Number,
+    String,
+    Bool,
+    Nil,
+    Dynamic,
+}
+
+pub fn infer(expr: &Expression) -> Type {
+    infer_with(expr, &[])
+}
+
+// Like infer, given the types of the locals in the innermost scope by the
+// slot the resolver gave them. It's up to the caller to make sure they hold,
+// the JIT checks a function's arguments before running its compiled code.
+pub fn infer_with(expr: &Expression, locals: &[Type]) -> Type {
+    infer_in(expr, locals, false)
+}
+
+// Like infer_with, guessing that fields read off `this` hold numbers. The JIT
+// guards every one of those reads and leaves the call to the interpreter
+// when the guess turns out wrong.
+pub fn speculate_with(expr: &Expression, locals: &[Type]) -> Type {
+    infer_in(expr, locals, true)
+}
+
+fn infer_in(expr: &Expression, locals: &[Type], speculate: bool) -> Type {
+    let infer = |expr| infer_in(expr, locals, speculate);
+    match expr {
+        Expression::Number(_) => Type::Number,
+        Expression::String(_) => Type::String,
+        Expression::Bool(_) => Type::Bool,
+        Expression::Nil => Type::Nil,
+        Expression::Variable(variable) => match variable.local {
+            Some(Local { depth: 0, slot }) if slot < locals.len() => locals[slot],
+            _ => Type::Dynamic,
+        },
+        Expression::Get(object, ..) if speculate && matches!(**object, Expression::This(_)) => {
+            Type::Number
+        }
+        Expression::This(_) | Expression::Call(..) | Expression::Get(..) => Type::Dynamic,
+        // an assignment evaluates to the assigned value
+        Expression::Assign(_, value) | Expression::Set(_, _, value, _) => infer(value),
+        Expression::Grouping(expression) => infer(expression),
+        Expression::Stringify(_) => Type::String,
+        Expression::Unary(operator, expression) => match (operator, infer(expression)) {
+            (TokenKind::Minus, Type::Number) => Type::Number,
+            // `!` always produces a bool no matter what it is applied to
+            (TokenKind::Bang, _) => Type::Bool,
+            _ => Type::Dynamic,
+        },
+        Expression::Binary(left, operator, right) => match (infer(left), operator, infer(right)) {
+            (
+                Type::Number,
+                TokenKind::Plus | TokenKind::Minus | TokenKind::Star | TokenKind::Slash,
+                Type::Number,
+            ) => Type::Number,
+            (Type::String, TokenKind::Plus, Type::String) => Type::String,
+            (
+                Type::Number,
+                TokenKind::Greater
+                | TokenKind::GreaterEqual
+                | TokenKind::Less
+                | TokenKind::LessEqual,
+                Type::Number,
+            ) => Type::Bool,
+            // equality is defined between any two values
+            (_, TokenKind::IsEqual | TokenKind::NotBang, _) => Type::Bool,
+            _ => Type::Dynamic,
+        },
+    }
+}
+
+// Types of the locals of a straight-line run of declarations and assignments
+// in a function's own scope, carrying on from the given parameter types. None
+// when there is anything else in it or a local could end up holding values
+// of more than one type, so that only the entry has to be guarded.
+pub fn infer_locals(statements: &[Statement], params: &[Type]) -> Option<Vec<Type>> {
+    locals_in(statements, params, false)
+}
+
+// infer_locals going by speculate_with
+pub fn speculate_locals(statements: &[Statement], params: &[Type]) -> Option<Vec<Type>> {
+    locals_in(statements, params, true)
+}
+
+fn locals_in(statements: &[Statement], params: &[Type], speculate: bool) -> Option<Vec<Type>> {
+    let infer = |value, locals: &[Type]| infer_in(value, locals, speculate);
+    let mut locals = params.to_vec();
+    for statement in statements {
+        match statement {
+            // the resolver hands out slots in declaration order
+            Statement::Var(_, Some(value)) => locals.push(infer(value, &locals)),
+            Statement::Expression(Expression::Assign(variable, value)) => match variable.local {
+                Some(Local { depth: 0, slot })
+                    if slot < locals.len() && infer(value, &locals) == locals[slot] => {}
+                _ => return None,
+            },
+            _ => return None,
+        }
+    }
+    Some(locals)
+}
@@ -0,0 +1,677 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+use std::rc::Rc;
+
+use crate::ast::{Expression, Function, Identifier, Local, Statement, Variable};
+use crate::lexer::TokenKind;
+use crate::limits::{Limits, Meter};
+use crate::object::{CacheStats, Instance, Property, Shape};
+use crate::symbol::Symbol;
+use crate::tier::{Counters, Tier, DEFAULT_JIT_THRESHOLD};
+
+#[derive(Debug, Clone)]
+pub enum Value {
+    Number(f64),
+    String(String),
+    Bool(bool),
+    Nil,
+    Function(Rc<Closure>),
+    Class(Rc<Class>),
+    Instance(Rc<RefCell<Instance>>),
+}
+
+// functions, classes and instances are equal only to themselves
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Nil, Value::Nil) => true,
+            (Value::Function(a), Value::Function(b)) => Rc::ptr_eq(a, b),
+            (Value::Class(a), Value::Class(b)) => Rc::ptr_eq(a, b),
+            (Value::Instance(a), Value::Instance(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+impl Value {
+    // Lox treats nil and false as falsey and everything else as truthy
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Bool(false))
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Number(num) => write!(f, "{}", num),
+            Value::String(s) => write!(f, "{}", s),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Nil => write!(f, "nil"),
+            Value::Function(function) => write!(f, "<fn {}>", function.declaration.name.name),
+            Value::Class(class) => write!(f, "{}", class.name),
+            Value::Instance(instance) => write!(f, "{} instance", instance.borrow().class().name),
+        }
+    }
+}
+
+// A function together with the environment it was declared in
+pub struct Closure {
+    pub declaration: Rc<Function>,
+    closure: Option<Rc<RefCell<Environment>>>,
+    // initializers always return the instance they were called on
+    is_initializer: bool,
+}
+
+impl Closure {
+    pub fn arity(&self) -> usize {
+        self.declaration.params.len()
+    }
+
+    pub fn tier(&self) -> Tier {
+        self.declaration.tier.tier()
+    }
+
+    pub fn counters(&self) -> Counters {
+        self.declaration.tier.counters()
+    }
+
+    // the instance a bound method runs on
+    fn this(&self) -> Option<Rc<RefCell<Instance>>> {
+        match self.closure.as_ref()?.borrow().values.first() {
+            Some(Value::Instance(instance)) => Some(instance.clone()),
+            _ => None,
+        }
+    }
+
+    // wraps the method in a scope holding `this`, matching the extra scope
+    // the resolver puts around every method
+    fn bind(&self, instance: Rc<RefCell<Instance>>) -> Closure {
+        let this = Environment {
+            values: vec![Value::Instance(instance)],
+            enclosing: self.closure.clone(),
+        };
+        Closure {
+            declaration: self.declaration.clone(),
+            closure: Some(Rc::new(RefCell::new(this))),
+            is_initializer: self.is_initializer,
+        }
+    }
+}
+
+// closures can end up in their own environment, so only the name is printed
+impl fmt::Debug for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<fn {}>", self.declaration.name.name)
+    }
+}
+
+pub struct Class {
+    pub name: Identifier,
+    methods: HashMap<Identifier, Rc<Closure>>,
+    // shape every instance of the class starts out with
+    shape: Rc<Shape>,
+}
+
+impl Class {
+    pub fn shape(&self) -> &Rc<Shape> {
+        &self.shape
+    }
+
+    pub fn find_method(&self, name: &Identifier) -> Option<&Rc<Closure>> {
+        self.methods.get(name)
+    }
+}
+
+impl fmt::Debug for Class {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuntimeError {
+    OperandMustBeNumber,
+    OperandsMustBeNumbers,
+    OperandsMustBeNumbersOrStrings,
+    UndefinedVariable(Identifier),
+    UndefinedProperty(Identifier),
+    NotCallable,
+    ArityMismatch { expected: usize, got: usize },
+    OnlyInstancesHaveProperties,
+    OnlyInstancesHaveFields,
+    Unsupported(&'static str),
+    // a run went over one of its Limits
+    OutOfFuel,
+    Timeout,
+    OutOfMemory,
+    StackOverflow,
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuntimeError::OperandMustBeNumber => write!(f, "Operand must be a number."),
+            RuntimeError::OperandsMustBeNumbers => write!(f, "Operands must be numbers."),
+            RuntimeError::OperandsMustBeNumbersOrStrings => {
+                write!(f, "Operands must be two numbers or two strings.")
+            }
+            RuntimeError::UndefinedVariable(name) => {
+                write!(f, "Undefined variable '{}'.", name)
+            }
+            RuntimeError::UndefinedProperty(name) => {
+                write!(f, "Undefined property '{}'.", name)
+            }
+            RuntimeError::NotCallable => write!(f, "Can only call functions and classes."),
+            RuntimeError::ArityMismatch { expected, got } => {
+                write!(f, "Expected {} arguments but got {}.", expected, got)
+            }
+            RuntimeError::OnlyInstancesHaveProperties => {
+                write!(f, "Only instances have properties.")
+            }
+            RuntimeError::OnlyInstancesHaveFields => write!(f, "Only instances have fields."),
+            RuntimeError::Unsupported(what) => write!(f, "{} is not supported yet.", what),
+            RuntimeError::OutOfFuel => write!(f, "Out of fuel."),
+            RuntimeError::Timeout => write!(f, "Execution timed out."),
+            RuntimeError::OutOfMemory => write!(f, "Out of memory."),
+            RuntimeError::StackOverflow => write!(f, "Stack overflow."),
+        }
+    }
+}
+
+pub type EvalResult = Result<Value, RuntimeError>;
+
+// Operators live outside the interpreter so passes that evaluate code ahead
+// of time, like constant folding, share the exact runtime semantics
+pub fn unary(operator: &TokenKind, value: Value) -> EvalResult {
+    match operator {
+        TokenKind::Minus => match value {
+            Value::Number(num) => Ok(Value::Number(-num)),
+            _ => Err(RuntimeError::OperandMustBeNumber),
+        },
+        TokenKind::Bang => Ok(Value::Bool(!value.is_truthy())),
+        _ => Err(RuntimeError::Unsupported("unary operator")),
+    }
+}
+
+pub fn binary(left: Value, operator: &TokenKind, right: Value) -> EvalResult {
+    match operator {
+        TokenKind::And => return Ok(if left.is_truthy() { right } else { left }),
+        TokenKind::Or => return Ok(if left.is_truthy() { left } else { right }),
+        TokenKind::IsEqual => return Ok(Value::Bool(left == right)),
+        TokenKind::NotBang => return Ok(Value::Bool(left != right)),
+        TokenKind::Plus => {
+            if let (Value::String(left), Value::String(right)) = (&left, &right) {
+                return Ok(Value::String(format!("{}{}", left, right)));
+            }
+        }
+        _ => {}
+    }
+
+    let (left, right) = match (left, right) {
+        (Value::Number(left), Value::Number(right)) => (left, right),
+        _ if *operator == TokenKind::Plus => {
+            return Err(RuntimeError::OperandsMustBeNumbersOrStrings)
+        }
+        _ => return Err(RuntimeError::OperandsMustBeNumbers),
+    };
+
+    match operator {
+        TokenKind::Plus => Ok(Value::Number(left + right)),
+        TokenKind::Minus => Ok(Value::Number(left - right)),
+        TokenKind::Slash => Ok(Value::Number(left / right)),
+        TokenKind::Star => Ok(Value::Number(left * right)),
+        TokenKind::Greater => Ok(Value::Bool(left > right)),
+        TokenKind::GreaterEqual => Ok(Value::Bool(left >= right)),
+        TokenKind::Less => Ok(Value::Bool(left < right)),
+        TokenKind::LessEqual => Ok(Value::Bool(left <= right)),
+        _ => Err(RuntimeError::Unsupported("binary operator")),
+    }
+}
+
+pub type ExecResult = Result<(), RuntimeError>;
+
+// Why a statement stopped before running to completion, either an error or
+// a `return` unwinding to the call it belongs to
+enum Unwind {
+    Error(RuntimeError),
+    Return(Value),
+}
+
+impl From<RuntimeError> for Unwind {
+    fn from(error: RuntimeError) -> Unwind {
+        Unwind::Error(error)
+    }
+}
+
+type Flow = Result<(), Unwind>;
+
+// Locals of a single scope, stored by the slot the resolver gave them
+#[derive(Default)]
+struct Environment {
+    values: Vec<Value>,
+    enclosing: Option<Rc<RefCell<Environment>>>,
+}
+
+impl Environment {
+    fn ancestor(env: &Rc<RefCell<Environment>>, depth: usize) -> Rc<RefCell<Environment>> {
+        let mut env = env.clone();
+        for _ in 0..depth {
+            let enclosing = env
+                .borrow()
+                .enclosing
+                .clone()
+                .expect("resolved depth is deeper than the environment chain");
+            env = enclosing;
+        }
+        env
+    }
+}
+
+// Tree-walking evaluator used as the baseline execution tier. Code runs here
+// until it is hot enough to be worth compiling with the JIT.
+pub struct Interpreter {
+    globals: HashMap<Identifier, Value>,
+    // None while executing top level code
+    environment: Option<Rc<RefCell<Environment>>>,
+    // the function running right now, None at top level
+    function: Option<Rc<Function>>,
+    // calls and loop iterations before a function is compiled
+    jit_threshold: u64,
+    // hits and misses of the inline caches in property access sites
+    cache_stats: CacheStats,
+    // what operators registered with the parser do, by spelling
+    unary_operators: HashMap<Identifier, fn(Value) -> EvalResult>,
+    binary_operators: HashMap<Identifier, fn(Value, Value) -> EvalResult>,
+    meter: Meter,
+}
+
+impl Default for Interpreter {
+    fn default() -> Interpreter {
+        Interpreter {
+            globals: HashMap::new(),
+            environment: None,
+            function: None,
+            jit_threshold: DEFAULT_JIT_THRESHOLD,
+            cache_stats: CacheStats::default(),
+            unary_operators: HashMap::new(),
+            binary_operators: HashMap::new(),
+            meter: Meter::default(),
+        }
+    }
+}
+
+impl Interpreter {
+    pub fn new() -> Interpreter {
+        Interpreter::default()
+    }
+
+    pub fn with_limits(limits: Limits) -> Interpreter {
+        Interpreter {
+            meter: Meter::new(limits),
+            ..Interpreter::default()
+        }
+    }
+
+    // applies from the next call on
+    pub fn set_jit_threshold(&mut self, threshold: u64) {
+        self.jit_threshold = threshold;
+    }
+
+    pub fn execute(&mut self, statements: &[Statement]) -> ExecResult {
+        self.meter.start();
+        match self.execute_statements(statements) {
+            Err(Unwind::Error(error)) => Err(error),
+            // the resolver rejects top level returns, so there is no caller
+            // left to unwind to
+            Ok(()) | Err(Unwind::Return(_)) => Ok(()),
+        }
+    }
+
+    pub fn define_unary_operator(&mut self, spelling: &str, operator: fn(Value) -> EvalResult) {
+        self.unary_operators
+            .insert(Symbol::intern(spelling), operator);
+    }
+
+    pub fn define_binary_operator(
+        &mut self,
+        spelling: &str,
+        operator: fn(Value, Value) -> EvalResult,
+    ) {
+        self.binary_operators
+            .insert(Symbol::intern(spelling), operator);
+    }
+
+    pub fn globals(&self) -> impl Iterator<Item = (&Identifier, &Value)> {
+        self.globals.iter()
+    }
+
+    // hit and miss counts summed over every property access this
+    // interpreter has run
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache_stats
+    }
+
+    fn execute_statements(&mut self, statements: &[Statement]) -> Flow {
+        for statement in statements {
+            self.execute_statement(statement)?;
+        }
+        Ok(())
+    }
+
+    fn execute_in(&mut self, statements: &[Statement], environment: Environment) -> Flow {
+        self.meter.allocate(
+            mem::size_of::<Environment>() + environment.values.len() * mem::size_of::<Value>(),
+        )?;
+        let previous = self.environment.replace(Rc::new(RefCell::new(environment)));
+        let result = self.execute_statements(statements);
+        self.environment = previous;
+        result
+    }
+
+    fn execute_block(&mut self, statements: &[Statement]) -> Flow {
+        let block = Environment {
+            values: Vec::new(),
+            enclosing: self.environment.clone(),
+        };
+        self.execute_in(statements, block)
+    }
+
+    fn define(&mut self, name: Identifier, value: Value) -> ExecResult {
+        self.meter.allocate(mem::size_of::<Value>())?;
+        match &self.environment {
+            Some(env) => env.borrow_mut().values.push(value),
+            None => {
+                self.globals.insert(name, value);
+            }
+        }
+        Ok(())
+    }
+
+    fn closure(
+        &mut self,
+        declaration: &Rc<Function>,
+        is_initializer: bool,
+    ) -> Result<Rc<Closure>, RuntimeError> {
+        self.meter.allocate(mem::size_of::<Closure>())?;
+        Ok(Rc::new(Closure {
+            declaration: declaration.clone(),
+            closure: self.environment.clone(),
+            is_initializer,
+        }))
+    }
+
+    fn execute_statement(&mut self, statement: &Statement) -> Flow {
+        match statement {
+            Statement::Expression(expression) => {
+                self.evaluate_expression(expression)?;
+            }
+            Statement::Print(expression) => {
+                let value = self.evaluate_expression(expression)?;
+                println!("{}", value);
+            }
+            Statement::Var(name, initializer) => {
+                let value = match initializer {
+                    Some(initializer) => self.evaluate_expression(initializer)?,
+                    None => Value::Nil,
+                };
+                self.define(name.name.clone(), value)?;
+            }
+            Statement::Block(statements) => self.execute_block(statements)?,
+            Statement::If(condition, then_branch, else_branch) => {
+                if self.evaluate_expression(condition)?.is_truthy() {
+                    self.execute_statement(then_branch)?;
+                } else if let Some(else_branch) = else_branch {
+                    self.execute_statement(else_branch)?;
+                }
+            }
+            Statement::While(condition, body) => {
+                while self.evaluate_expression(condition)?.is_truthy() {
+                    self.execute_statement(body)?;
+                    self.meter.tick()?;
+                    if let Some(function) = &self.function {
+                        function.tier.record_backedge();
+                    }
+                }
+            }
+            Statement::Function(function) => {
+                let closure = self.closure(function, false)?;
+                self.define(function.name.name.clone(), Value::Function(closure))?;
+            }
+            Statement::Return(_, value) => {
+                let value = match value {
+                    Some(value) => self.evaluate_expression(value)?,
+                    None => Value::Nil,
+                };
+                return Err(Unwind::Return(value));
+            }
+            Statement::Class(name, methods) => {
+                let methods = methods
+                    .iter()
+                    .map(|method| {
+                        let is_initializer = method.name.name.as_str() == "init";
+                        Ok((
+                            method.name.name.clone(),
+                            self.closure(method, is_initializer)?,
+                        ))
+                    })
+                    .collect::<Result<_, RuntimeError>>()?;
+                let class = Class {
+                    name: name.name.clone(),
+                    methods,
+                    shape: Shape::root(),
+                };
+                self.define(name.name.clone(), Value::Class(Rc::new(class)))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn call(&mut self, callee: Value, arguments: Vec<Value>) -> EvalResult {
+        match callee {
+            Value::Function(function) => self.call_function(&function, arguments),
+            Value::Class(class) => {
+                self.meter.allocate(mem::size_of::<Instance>())?;
+                let instance = Rc::new(RefCell::new(Instance::new(class.clone())));
+                match class.find_method(&Symbol::intern("init")) {
+                    Some(initializer) => {
+                        self.call_function(&initializer.bind(instance.clone()), arguments)?;
+                    }
+                    None if !arguments.is_empty() => {
+                        return Err(RuntimeError::ArityMismatch {
+                            expected: 0,
+                            got: arguments.len(),
+                        })
+                    }
+                    None => {}
+                }
+                Ok(Value::Instance(instance))
+            }
+            _ => Err(RuntimeError::NotCallable),
+        }
+    }
+
+    fn call_function(&mut self, function: &Closure, arguments: Vec<Value>) -> EvalResult {
+        if arguments.len() != function.arity() {
+            return Err(RuntimeError::ArityMismatch {
+                expected: function.arity(),
+                got: arguments.len(),
+            });
+        }
+
+        self.meter.tick()?;
+        let declaration = &function.declaration;
+        declaration
+            .tier
+            .record_call(declaration, self.jit_threshold);
+        let this = function.this();
+        if let Some(value) =
+            declaration
+                .tier
+                .call_compiled(&arguments, this.as_ref(), &mut self.cache_stats)
+        {
+            return Ok(value);
+        }
+
+        self.meter.enter_call()?;
+        // parameters take the first slots of the function's scope
+        let environment = Environment {
+            values: arguments,
+            enclosing: function.closure.clone(),
+        };
+        let caller = self.function.replace(declaration.clone());
+        let result = self.execute_in(&function.declaration.body, environment);
+        self.function = caller;
+        self.meter.exit_call();
+        let value = match result {
+            Ok(()) => Value::Nil,
+            Err(Unwind::Return(value)) => value,
+            Err(Unwind::Error(error)) => return Err(error),
+        };
+
+        if function.is_initializer {
+            let this = function
+                .closure
+                .as_ref()
+                .expect("initializers are always bound to an instance");
+            return Ok(this.borrow().values[0].clone());
+        }
+        Ok(value)
+    }
+
+    fn look_up_variable(&self, variable: &Variable) -> EvalResult {
+        match (variable.local, &self.environment) {
+            (Some(Local { depth, slot }), Some(env)) => {
+                Ok(Environment::ancestor(env, depth).borrow().values[slot].clone())
+            }
+            _ => self
+                .globals
+                .get(&variable.name)
+                .cloned()
+                .ok_or_else(|| RuntimeError::UndefinedVariable(variable.name.clone())),
+        }
+    }
+
+    fn assign_variable(&mut self, variable: &Variable, value: Value) -> ExecResult {
+        match (variable.local, &self.environment) {
+            (Some(Local { depth, slot }), Some(env)) => {
+                Environment::ancestor(env, depth).borrow_mut().values[slot] = value;
+            }
+            _ => match self.globals.get_mut(&variable.name) {
+                Some(global) => *global = value,
+                None => return Err(RuntimeError::UndefinedVariable(variable.name.clone())),
+            },
+        }
+        Ok(())
+    }
+
+    pub fn evaluate(&mut self, expr: &Expression) -> EvalResult {
+        self.meter.start();
+        self.evaluate_expression(expr)
+    }
+
+    fn evaluate_expression(&mut self, expr: &Expression) -> EvalResult {
+        match expr {
+            Expression::Number(num) => Ok(Value::Number(*num)),
+            Expression::String(s) => {
+                self.meter.allocate(s.len())?;
+                Ok(Value::String(s.clone()))
+            }
+            Expression::Bool(b) => Ok(Value::Bool(*b)),
+            Expression::Nil => Ok(Value::Nil),
+            Expression::Variable(variable) => self.look_up_variable(variable),
+            Expression::This(variable) => self.look_up_variable(variable),
+            Expression::Grouping(expression) => self.evaluate_expression(expression),
+            Expression::Stringify(expression) => {
+                let string = self.evaluate_expression(expression)?.to_string();
+                self.meter.allocate(string.len())?;
+                Ok(Value::String(string))
+            }
+            Expression::Unary(operator, expression) => {
+                let value = self.evaluate_expression(expression)?;
+                match operator {
+                    TokenKind::Operator(name) => match self.unary_operators.get(name) {
+                        Some(operator) => operator(value),
+                        None => Err(RuntimeError::Unsupported("unary operator")),
+                    },
+                    _ => unary(operator, value),
+                }
+            }
+            // the right operand is only evaluated when the left one doesn't
+            // decide the result
+            Expression::Binary(left, operator @ (TokenKind::And | TokenKind::Or), right) => {
+                let left = self.evaluate_expression(left)?;
+                match (operator, left.is_truthy()) {
+                    (TokenKind::And, false) | (TokenKind::Or, true) => Ok(left),
+                    _ => self.evaluate_expression(right),
+                }
+            }
+            Expression::Binary(left, operator, right) => {
+                let left = self.evaluate_expression(left)?;
+                let right = self.evaluate_expression(right)?;
+                // concatenation is the one operator that allocates
+                if let (TokenKind::Plus, Value::String(left), Value::String(right)) =
+                    (operator, &left, &right)
+                {
+                    self.meter.allocate(left.len() + right.len())?;
+                }
+                match operator {
+                    TokenKind::Operator(name) => match self.binary_operators.get(name) {
+                        Some(operator) => operator(left, right),
+                        None => Err(RuntimeError::Unsupported("binary operator")),
+                    },
+                    _ => binary(left, operator, right),
+                }
+            }
+            Expression::Call(callee, arguments) => {
+                let callee = self.evaluate_expression(callee)?;
+                let arguments = arguments
+                    .iter()
+                    .map(|argument| self.evaluate_expression(argument))
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.call(callee, arguments)
+            }
+            Expression::Get(object, name, cache) => {
+                let instance = match self.evaluate_expression(object)? {
+                    Value::Instance(instance) => instance,
+                    _ => return Err(RuntimeError::OnlyInstancesHaveProperties),
+                };
+
+                let property = cache.get(&instance.borrow(), name, &mut self.cache_stats);
+                match property {
+                    Some(Property::Field(slot)) => Ok(instance.borrow().field(slot).clone()),
+                    Some(Property::Method(method)) => {
+                        Ok(Value::Function(Rc::new(method.bind(instance))))
+                    }
+                    None => Err(RuntimeError::UndefinedProperty(name.clone())),
+                }
+            }
+            Expression::Set(object, name, value, cache) => {
+                let instance = match self.evaluate_expression(object)? {
+                    Value::Instance(instance) => instance,
+                    _ => return Err(RuntimeError::OnlyInstancesHaveFields),
+                };
+                let value = self.evaluate_expression(value)?;
+                if instance.borrow().get(name).is_none() {
+                    self.meter.allocate(mem::size_of::<Value>())?;
+                }
+                cache.set(
+                    &mut instance.borrow_mut(),
+                    name,
+                    value.clone(),
+                    &mut self.cache_stats,
+                );
+                Ok(value)
+            }
+            Expression::Assign(variable, value) => {
+                let value = self.evaluate_expression(value)?;
+                self.assign_variable(variable, value.clone())?;
+                Ok(value)
+            }
+        }
+    }
+}
@@ -1,23 +1,107 @@
+use cranelift::codegen::ir::FuncRef;
 use cranelift::prelude::*;
 use cranelift_jit::{JITBuilder, JITModule};
 use cranelift_module::{Linkage, Module};
 
+use std::cell::RefCell;
 use std::mem;
+use std::rc::Rc;
 
-use crate::ast::Expression;
+use crate::ast::{Expression, Function, Local, Statement};
+use crate::infer::{infer, speculate_locals, speculate_with, Type};
+use crate::interpreter::Value as LoxValue;
 use crate::lexer::TokenKind;
+use crate::object::{CacheStats, Instance};
 use crate::parser::Parser;
 
 pub struct JIT {
     builder_context: FunctionBuilderContext,
     context: codegen::Context,
     module: JITModule,
+    // used to give every compiled function a unique symbol in the module
+    compiled_count: usize,
+}
+
+// What compiled code gets besides its arguments
+#[repr(C)]
+struct Frame {
+    // the instance a method is bound to, null for anything else
+    this: *const RefCell<Instance>,
+    stats: *mut CacheStats,
+    // set by code that ran into something it wasn't compiled for, like a
+    // shape none of its guards know. What it returns is garbage then.
+    bailed: bool,
+}
+
+// Called from compiled code with the instance of its frame
+extern "C" fn shape_of(this: *const RefCell<Instance>) -> u64 {
+    unsafe { &*this }.borrow().shape().id() as u64
+}
+
+extern "C" fn number_field(this: *const RefCell<Instance>, slot: u64, frame: *mut Frame) -> f64 {
+    match unsafe { &*this }.borrow().field(slot as usize) {
+        LoxValue::Number(num) => *num,
+        _ => {
+            unsafe { (*frame).bailed = true };
+            0.0
+        }
+    }
+}
+
+// Native code for a Lox function. It takes the arguments as raw f64s, so
+// callers have to check they are all numbers first.
+pub struct CompiledFunction {
+    // the module holds nothing but this function, so its code can be
+    // unmapped as soon as the function goes away
+    module: Option<JITModule>,
+    code: extern "C" fn(*const f64, *mut Frame) -> f64,
+    arity: usize,
+    // methods reading fields need the instance they are bound to
+    uses_this: bool,
+}
+
+impl CompiledFunction {
+    // None when the code bailed out. It only reads locals and fields, so the
+    // call can simply be run again in the interpreter.
+    pub fn call(
+        &self,
+        arguments: &[f64],
+        this: Option<&Rc<RefCell<Instance>>>,
+        stats: &mut CacheStats,
+    ) -> Option<f64> {
+        assert_eq!(
+            arguments.len(),
+            self.arity,
+            "compiled call with wrong arity"
+        );
+        let this = match this {
+            Some(this) => Rc::as_ptr(this),
+            None if self.uses_this => return None,
+            None => std::ptr::null(),
+        };
+        let mut frame = Frame {
+            this,
+            stats,
+            bailed: false,
+        };
+        let result = (self.code)(arguments.as_ptr(), &mut frame);
+        (!frame.bailed).then_some(result)
+    }
+}
+
+impl Drop for CompiledFunction {
+    fn drop(&mut self) {
+        if let Some(module) = self.module.take() {
+            // the code is only reachable through self
+            unsafe { module.free_memory() }
+        }
+    }
 }
 
 impl Default for JIT {
     fn default() -> Self {
         let mut flag_builder = settings::builder();
-        flag_builder.set("is_pic", "false");
+        flag_builder.set("is_pic", "false").unwrap();
 
         let isa_builder = cranelift_native::builder()
             .unwrap_or_else(|msg| panic!("host machine is not supported: {}", msg));
@@ -26,33 +110,96 @@ impl Default for JIT {
             .finish(settings::Flags::new(flag_builder))
             .unwrap();
 
-        let builder = JITBuilder::with_isa(isa, cranelift_module::default_libcall_names());
+        let mut builder = JITBuilder::with_isa(isa, cranelift_module::default_libcall_names());
+        builder.symbol("shape_of", shape_of as *const u8);
+        builder.symbol("number_field", number_field as *const u8);
 
         let module = JITModule::new(builder);
         JIT {
             builder_context: FunctionBuilderContext::new(),
             context: module.make_context(),
             module,
+            compiled_count: 0,
         }
     }
 }
 
 impl JIT {
+    /// Unmaps the code of every function this JIT compiled. Without it the
+    /// code lives until the process exits.
+    ///
+    /// # Safety
+    ///
+    /// None of the functions returned by `compile` or `compile_expression`
+    /// may be called afterwards.
+    pub unsafe fn free_memory(self) {
+        self.module.free_memory()
+    }
+
     pub fn compile(&mut self, src: &str) -> Result<fn() -> f64, String> {
         let mut parser = Parser::new(src);
-        let expression = parser.parse().unwrap();
+        let expression = parser.parse().map_err(|e| format!("{:?}", e))?;
 
-        self.translate(expression)?;
+        self.compile_expression(expression)
+    }
+
+    pub fn compile_expression(&mut self, expr: Expression) -> Result<fn() -> f64, String> {
+        if let Err(e) = self.specialize(&expr) {
+            self.reset_context();
+            return Err(e);
+        }
+        let code = self.finish()?;
+
+        unsafe { Ok(mem::transmute::<*const u8, fn() -> f64>(code)) }
+    }
+
+    // Compiles a Lox function into this JIT's module, which the result then
+    // owns. Only straight-line functions of numeric locals ending in a return
+    // are supported so far. Parameters are specialized to numbers, callers
+    // check the arguments. Fields read off `this` are guessed to be numbers
+    // too, and guarded by the shapes their inline caches have seen.
+    pub fn compile_function(mut self, function: &Function) -> Result<CompiledFunction, String> {
+        let (statements, result) = match function.body.as_slice() {
+            [statements @ .., Statement::Return(_, Some(result))] => (statements, result),
+            _ => return Err("only functions ending in a return are compiled".to_string()),
+        };
+        let arity = function.params.len();
+        // Compiled code never ticks the Meter, so fuel, timeouts and the call
+        // depth and stack guards only hold because loops and calls are
+        // refused here and in translate. Compiling either means metering it.
+        let locals = speculate_locals(statements, &vec![Type::Number; arity])
+            .ok_or("only straight-line functions are compiled")?;
+        if let Some(ty) = locals.iter().find(|ty| **ty != Type::Number) {
+            return Err(format!("cannot specialize {:?} local", ty));
+        }
+        let uses_this = match speculate_with(result, &locals) {
+            Type::Number => self.translate(statements, result, Some(arity))?,
+            ty => return Err(format!("cannot specialize function returning {:?}", ty)),
+        };
+        let code = self.finish()?;
 
+        Ok(CompiledFunction {
+            code: unsafe {
+                mem::transmute::<*const u8, extern "C" fn(*const f64, *mut Frame) -> f64>(code)
+            },
+            arity,
+            uses_this,
+            module: Some(self.module),
+        })
+    }
+
+    // defines the translated function in the module and returns its code
+    fn finish(&mut self) -> Result<*const u8, String> {
         // function must be declared to jit before they can be called or defined
         let id = self
             .module
             .declare_function(
-                &format!("jit_{}", src),
+                &format!("jit_{}", self.compiled_count),
                 Linkage::Export,
                 &self.context.func.signature,
             )
             .map_err(|e| e.to_string())?;
+        self.compiled_count += 1;
 
         self.module
             .define_function(id, &mut self.context)
@@ -62,14 +209,59 @@ impl JIT {
 
         self.module.finalize_definitions();
 
-        let code = self.module.get_finalized_function(id);
+        Ok(self.module.get_finalized_function(id))
+    }
+
+    // The Cranelift IR compile_expression would generate for an expression,
+    // without compiling it
+    pub fn clif(&mut self, expr: Expression) -> Result<String, String> {
+        let clif = self
+            .specialize(&expr)
+            .map(|()| self.context.func.display().to_string());
+        self.reset_context();
+        clif
+    }
+
+    fn specialize(&mut self, expr: &Expression) -> Result<(), String> {
+        // only code proven to always be a number gets compiled, so it can live
+        // in float registers as a raw f64. Anything polymorphic is rejected
+        // and keeps running in the interpreter
+        match infer(expr) {
+            Type::Number => self.translate(&[], expr, None).map(|_| ()),
+            ty => Err(format!("cannot specialize {:?} expression", ty)),
+        }
+    }
 
-        unsafe { Ok(mem::transmute(code)) }
+    // throws away a function that was translated but not defined, either
+    // because translation bailed out before the builder was finalized or
+    // because only its IR was wanted
+    fn reset_context(&mut self) {
+        self.module.clear_context(&mut self.context);
+        self.builder_context = FunctionBuilderContext::new();
     }
 
-    fn translate(&mut self, expr: Expression) -> Result<(), String> {
+    // Functions take a pointer to their arguments and one to their Frame,
+    // standalone expressions take nothing. The statements run before the
+    // expression is returned. Returns whether the code reads `this`.
+    fn translate(
+        &mut self,
+        statements: &[Statement],
+        expr: &Expression,
+        arity: Option<usize>,
+    ) -> Result<bool, String> {
         // The only literal blox supports for now is the number literal(f64)
         let float = AbiParam::new(types::F64).value_type;
+        let pointer = self.module.target_config().pointer_type();
+
+        let helpers = match arity {
+            Some(_) => {
+                let signature = &mut self.context.func.signature;
+                signature.params.push(AbiParam::new(pointer));
+                signature.params.push(AbiParam::new(pointer));
+                Some(self.declare_helpers()?)
+            }
+            None => None,
+        };
 
         self.context
             .func
@@ -89,48 +281,234 @@ impl JIT {
         // seal because block will have no predeccessors
         builder.seal_block(entry_block);
 
-        let mut translator = FunctionTranslator { builder };
+        // parameters are copied out of the argument array into variables,
+        // which the resolver's slots index straight into
+        let mut locals = Vec::new();
+        let mut properties = None;
+        if let (Some(arity), Some((shape_of, number_field))) = (arity, helpers) {
+            let arguments = builder.block_params(entry_block)[0];
+            let frame = builder.block_params(entry_block)[1];
+            let this = builder.ins().load(
+                pointer,
+                MemFlags::trusted(),
+                frame,
+                mem::offset_of!(Frame, this) as i32,
+            );
+            properties = Some(Properties {
+                frame,
+                this,
+                shape_of,
+                number_field,
+            });
+            for slot in 0..arity {
+                let offset = (slot * mem::size_of::<f64>()) as i32;
+                let value = builder
+                    .ins()
+                    .load(float, MemFlags::trusted(), arguments, offset);
+                let local = Variable::new(slot);
+                builder.declare_var(local, float);
+                builder.def_var(local, value);
+                locals.push(local);
+            }
+        }
+
+        let mut translator = FunctionTranslator {
+            builder,
+            locals,
+            pointer,
+            properties,
+            uses_this: false,
+        };
 
-        let ret = translator.translate_expression(expr).unwrap();
+        for statement in statements {
+            translator.translate_statement(statement)?;
+        }
+        let ret = translator.translate_expression(expr)?;
 
         translator.builder.ins().return_(&[ret]);
+        translator.builder.seal_all_blocks();
         translator.builder.finalize();
-        Ok(())
+        Ok(translator.uses_this)
     }
+
+    // imports the functions compiled code reads fields through into the
+    // function being translated
+    fn declare_helpers(&mut self) -> Result<(FuncRef, FuncRef), String> {
+        let pointer = self.module.target_config().pointer_type();
+        let mut declare = |name: &str, params: &[types::Type], ret: types::Type| {
+            let mut signature = self.module.make_signature();
+            for param in params {
+                signature.params.push(AbiParam::new(*param));
+            }
+            signature.returns.push(AbiParam::new(ret));
+            let id = self
+                .module
+                .declare_function(name, Linkage::Import, &signature)
+                .map_err(|e| e.to_string())?;
+            Ok::<_, String>(self.module.declare_func_in_func(id, &mut self.context.func))
+        };
+        let shape_of = declare("shape_of", &[pointer], types::I64)?;
+        let number_field = declare("number_field", &[pointer, types::I64, pointer], types::F64)?;
+        Ok((shape_of, number_field))
+    }
+}
+
+// What reading a field from compiled code needs
+struct Properties {
+    frame: Value,
+    this: Value,
+    shape_of: FuncRef,
+    number_field: FuncRef,
 }
 
 struct FunctionTranslator<'a> {
     builder: FunctionBuilder<'a>,
+    // the function's own scope, by slot
+    locals: Vec<Variable>,
+    pointer: types::Type,
+    // None outside functions, which have no frame
+    properties: Option<Properties>,
+    uses_this: bool,
 }
 
 impl<'a> FunctionTranslator<'a> {
-    fn translate_expression(&mut self, expr: Expression) -> Result<Value, ()> {
+    // declarations take the next slot, the same as in the resolver
+    fn translate_statement(&mut self, statement: &Statement) -> Result<(), String> {
+        match statement {
+            Statement::Var(_, Some(value)) => {
+                let value = self.translate_expression(value)?;
+                let local = Variable::new(self.locals.len());
+                self.builder.declare_var(local, types::F64);
+                self.builder.def_var(local, value);
+                self.locals.push(local);
+                Ok(())
+            }
+            Statement::Expression(expression) => self.translate_expression(expression).map(|_| ()),
+            _ => Err("only declarations and expressions are compiled".to_string()),
+        }
+    }
+
+    fn translate_expression(&mut self, expr: &Expression) -> Result<Value, String> {
         match expr {
-            Expression::Number(num) => Ok(self.builder.ins().f64const(num)),
-            Expression::Grouping(grouping_expression) => match *grouping_expression {
-                _ => Ok(self.translate_expression(*grouping_expression)?),
-            },
+            Expression::Number(num) => Ok(self.builder.ins().f64const(*num)),
+            Expression::Grouping(grouping_expression) => {
+                self.translate_expression(grouping_expression)
+            }
             Expression::Unary(operator, expression) => match operator {
-                TokenKind::Minus => match *expression {
+                TokenKind::Minus => match **expression {
                     Expression::Number(num) => Ok(self.builder.ins().f64const(-num)),
-                    _ => Err(eprintln!("Not correct value for negation")),
+                    ref expression => {
+                        let value = self.translate_expression(expression)?;
+                        Ok(self.builder.ins().fneg(value))
+                    }
                 },
-                _ => unimplemented!("just takes negative numbers for now"),
+                _ => Err("just takes negative numbers for now".to_string()),
             },
 
             Expression::Binary(left, operator, right) => {
-                let left = self.translate_expression(*left)?;
-                let right = self.translate_expression(*right)?;
+                let left = self.translate_expression(left)?;
+                let right = self.translate_expression(right)?;
 
                 match operator {
                     TokenKind::Plus => Ok(self.builder.ins().fadd(left, right)),
                     TokenKind::Minus => Ok(self.builder.ins().fsub(left, right)),
                     TokenKind::Slash => Ok(self.builder.ins().fdiv(left, right)),
                     TokenKind::Star => Ok(self.builder.ins().fmul(left, right)),
-                    _ => unimplemented!("other binary operations have not been implemented yet"),
+                    _ => Err("other binary operations have not been implemented yet".to_string()),
                 }
             }
-            _ => unimplemented!("implement once you have functions"),
+            // anything further out than the function's own scope may change
+            // between calls, so only its own locals are read directly
+            Expression::Variable(variable) => match variable.local {
+                Some(Local { depth: 0, slot }) if slot < self.locals.len() => {
+                    Ok(self.builder.use_var(self.locals[slot]))
+                }
+                _ => Err("only the function's own locals can be read".to_string()),
+            },
+            Expression::Assign(variable, value) => match variable.local {
+                Some(Local { depth: 0, slot }) if slot < self.locals.len() => {
+                    let value = self.translate_expression(value)?;
+                    self.builder.def_var(self.locals[slot], value);
+                    Ok(value)
+                }
+                _ => Err("only the function's own locals can be assigned".to_string()),
+            },
+            // methods find `this` in the scope right around their own
+            Expression::Get(object, _, cache) => match &**object {
+                Expression::This(this) if this.local == Some(Local { depth: 1, slot: 0 }) => {
+                    // a site that hasn't run yet gets no guards, so it bails
+                    // out and is compiled again once its cache knows more
+                    let slots = cache
+                        .field_slots()
+                        .ok_or("only sites that read fields of a few shapes are compiled")?;
+                    self.translate_field(&slots)
+                }
+                _ => Err("only fields of this can be read".to_string()),
+            },
+            _ => Err("implement once you have functions".to_string()),
         }
     }
+
+    // The inline cache of the site compiled into a guard per shape it has
+    // seen, each loading the field from the slot it has in that shape
+    fn translate_field(&mut self, slots: &[(usize, usize)]) -> Result<Value, String> {
+        let properties = self
+            .properties
+            .as_ref()
+            .ok_or("fields are only read in functions")?;
+        self.uses_this = true;
+        let (frame, this) = (properties.frame, properties.this);
+        let (shape_of, number_field) = (properties.shape_of, properties.number_field);
+
+        let call = self.builder.ins().call(shape_of, &[this]);
+        let shape = self.builder.inst_results(call)[0];
+        let done = self.builder.create_block();
+        self.builder.append_block_param(done, types::F64);
+
+        for &(id, slot) in slots {
+            let hit = self.builder.create_block();
+            let next = self.builder.create_block();
+            let matches = self.builder.ins().icmp_imm(IntCC::Equal, shape, id as i64);
+            self.builder.ins().brnz(matches, hit, &[]);
+            self.builder.ins().jump(next, &[]);
+
+            // counted the same as a hit in the interpreter
+            self.builder.switch_to_block(hit);
+            let stats = self.builder.ins().load(
+                self.pointer,
+                MemFlags::trusted(),
+                frame,
+                mem::offset_of!(Frame, stats) as i32,
+            );
+            let offset = mem::offset_of!(CacheStats, hits) as i32;
+            let hits = self
+                .builder
+                .ins()
+                .load(types::I64, MemFlags::trusted(), stats, offset);
+            let hits = self.builder.ins().iadd_imm(hits, 1);
+            self.builder
+                .ins()
+                .store(MemFlags::trusted(), hits, stats, offset);
+            let slot = self.builder.ins().iconst(types::I64, slot as i64);
+            let call = self.builder.ins().call(number_field, &[this, slot, frame]);
+            let value = self.builder.inst_results(call)[0];
+            self.builder.ins().jump(done, &[value]);
+
+            self.builder.switch_to_block(next);
+        }
+
+        // a shape none of the guards know
+        let bailed = self.builder.ins().iconst(types::I8, 1);
+        self.builder.ins().store(
+            MemFlags::trusted(),
+            bailed,
+            frame,
+            mem::offset_of!(Frame, bailed) as i32,
+        );
+        let garbage = self.builder.ins().f64const(0.0);
+        self.builder.ins().jump(done, &[garbage]);
+
+        self.builder.switch_to_block(done);
+        Ok(self.builder.block_params(done)[0])
+    }
 }
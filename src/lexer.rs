@@ -1,4 +1,12 @@
+use std::borrow::Cow;
+
+use unicode_xid::UnicodeXID;
+
+use crate::source::Span;
+use crate::symbol::Symbol;
+
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TokenKind {
     // Single Character Tokens
     LPar,
@@ -22,10 +30,14 @@ pub enum TokenKind {
     GreaterEqual,
     Less,
     LessEqual,
-
-    // Literals
-    Ident(String),
-    String(String),
+    // an operator registered through Lexer::add_operator
+    Operator(Symbol),
+
+    // Literals, string contents are read from the token's lexeme
+    Ident(Symbol),
+    String,
+    // a piece of string literal that ends where an interpolated `${` starts
+    Interpolation,
     Number(f64),
 
     // Keywords
@@ -45,67 +57,225 @@ pub enum TokenKind {
     True,
     Var,
     While,
+
+    // Trivia, kept in the token stream for tools but skipped by the parser
+    DocComment,
+
     Error,
     Eof,
 }
 
+// Tokens borrow their lexeme straight from the source, except for error
+// tokens whose lexeme is the error message. They are only ever written out,
+// a borrowed lexeme can't be read back from JSON that had to escape it.
 #[derive(Debug, Clone, PartialEq)]
-pub struct Token {
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Token<'src> {
     pub kind: TokenKind,
-    pub lexeme: String,
+    pub lexeme: &'src str,
     pub line: u64,
+    // counted in chars from 1, like line
+    pub column: u64,
+    // covers the whole token, including quotes and the like that the lexeme
+    // leaves out. For errors it covers the offending text
+    pub span: Span,
 }
 
-impl Token {
-    pub fn default_token() -> Token {
+impl<'src> Token<'src> {
+    // the text of a string or interpolation token with escapes applied, the
+    // lexeme itself holds the raw text between the quotes
+    pub fn value(&self) -> Cow<'src, str> {
+        unescape(self.lexeme)
+    }
+
+    pub fn default_token() -> Token<'src> {
         Token {
             kind: TokenKind::Error,
-            lexeme: "No token has been displayed yet".to_string(),
+            lexeme: "No token has been displayed yet",
             line: 0,
+            column: 0,
+            span: Span::default(),
         }
     }
 }
 
-pub struct Lexer {
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct LexerOptions {
+    // accept any Unicode XID identifier instead of just [A-Za-z_][A-Za-z0-9_]*
+    pub unicode_identifiers: bool,
+    // accept 0x and 0b literals, `_` digit separators and exponents
+    pub extended_numbers: bool,
+    // accept "${expression}" inside string literals
+    pub string_interpolation: bool,
+}
+
+// Only called on string contents the lexer already validated, so every
+// escape in raw is known to be well formed
+pub fn unescape(raw: &str) -> Cow<'_, str> {
+    if !raw.contains('\\') {
+        return Cow::Borrowed(raw);
+    }
+
+    let mut value = String::with_capacity(raw.len());
+    let mut chars = raw.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            value.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => value.push('\n'),
+            Some('t') => value.push('\t'),
+            Some('r') => value.push('\r'),
+            Some('u') => {
+                let code: String = chars.by_ref().skip(1).take_while(|c| *c != '}').collect();
+                let c = u32::from_str_radix(&code, 16)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .unwrap_or(char::REPLACEMENT_CHARACTER);
+                value.push(c);
+            }
+            Some(c) => value.push(c),
+            None => {}
+        }
+    }
+    Cow::Owned(value)
+}
+
+// `_` may only stand between two digits, so `1_`, `1__0`, `1_.5` and `0x_1`
+// are all rejected
+fn valid_separators(literal: &str, radix: u32) -> bool {
+    let bytes = literal.as_bytes();
+    let is_digit = |idx: usize| bytes.get(idx).is_some_and(|b| (*b as char).is_digit(radix));
+    (0..bytes.len())
+        .all(|idx| bytes[idx] != b'_' || (idx > 0 && is_digit(idx - 1) && is_digit(idx + 1)))
+}
+
+// start and current are byte offsets into src and always sit on a char
+// boundary, so slicing the source between them is always valid
+pub struct Lexer<'src> {
     start: usize,
     current: usize,
     line: u64,
-    src: String,
+    column: u64,
+    // position of the first char of the token being scanned
+    start_line: u64,
+    start_column: u64,
+    src: &'src str,
+    options: LexerOptions,
+    // one entry per interpolation we are inside of, counting the braces
+    // opened within it so we know which `}` ends the interpolation
+    interpolations: Vec<usize>,
+    // extra operator spellings, longest first so the longest match wins
+    operators: Vec<Symbol>,
 }
 
-impl Lexer {
-    pub fn new(src: &str) -> Lexer {
+impl<'src> Lexer<'src> {
+    pub fn new(src: &'src str) -> Lexer<'src> {
+        Lexer::with_options(src, LexerOptions::default())
+    }
+
+    pub fn with_options(src: &'src str, options: LexerOptions) -> Lexer<'src> {
         Lexer {
-            src: src.to_string(),
+            src,
+            options,
+            interpolations: Vec::new(),
+            operators: Vec::new(),
             current: 0,
             start: 0,
             line: 1,
+            column: 1,
+            start_line: 1,
+            start_column: 1,
+        }
+    }
+
+    // Makes the lexer produce Operator tokens for spelling. Registered
+    // operators are tried before the built in ones, so "^^" is a single token
+    // even when "^" is registered too. Spellings are limited to punctuation
+    // so they can't swallow identifiers, numbers or whitespace, and have to
+    // start with something no built in token starts with. Otherwise taking
+    // over a prefix of valid source would change what it means, "=-" would
+    // turn `a=-1` into an operator and "(-" would break `f(-1)`.
+    pub fn add_operator(&mut self, spelling: &str) -> Result<(), &'static str> {
+        if spelling.is_empty() || !spelling.chars().all(|c| c.is_ascii_punctuation()) {
+            return Err("Operators must be made of punctuation.");
+        }
+
+        if ["\"", "//", "/*"]
+            .iter()
+            .any(|start| spelling.contains(start))
+        {
+            return Err("Operators can't start strings or comments.");
+        }
+        if Lexer::new(spelling).next_token().kind != TokenKind::Error {
+            return Err("Operators can't replace built in tokens.");
+        }
+
+        let operator = Symbol::intern(spelling);
+        if !self.operators.contains(&operator) {
+            self.operators.push(operator);
+            self.operators
+                .sort_by_key(|operator| std::cmp::Reverse(operator.as_str().len()));
+        }
+        Ok(())
+    }
+
+    fn registered_operator(&mut self) -> Option<Token<'src>> {
+        let rest = &self.src[self.current..];
+        let operator = self
+            .operators
+            .iter()
+            .find(|operator| rest.starts_with(operator.as_str()))?
+            .clone();
+
+        // spellings are ASCII, so every byte is a char
+        for _ in 0..operator.as_str().len() {
+            self.next_char();
         }
+        Some(self.make_token(TokenKind::Operator(operator)))
+    }
+
+    // called after consuming a '\n'
+    fn newline(&mut self) {
+        self.line += 1;
+        self.column = 1;
     }
 
     fn char_at(&self, idx: usize) -> Option<char> {
-        self.src.chars().nth(idx)
+        self.src[idx..].chars().next()
     }
 
     fn next_char(&mut self) -> Option<char> {
-        self.current += 1;
-        self.char_at(self.current - 1)
+        let c = self.char_at(self.current)?;
+        self.current += c.len_utf8();
+        self.column += 1;
+        Some(c)
     }
 
     fn peek(&self) -> Option<char> {
         self.char_at(self.current)
     }
 
-    fn peek_is_digit(&self) -> bool {
-        match self.peek() {
-            Some(c) => c.is_digit(10),
-            None => false,
+    fn peek_next(&self) -> Option<char> {
+        self.peek_nth(1)
+    }
+
+    fn peek_nth(&self, n: usize) -> Option<char> {
+        self.src[self.current..].chars().nth(n)
+    }
+
+    fn is_ident_start(&self, c: char) -> bool {
+        match self.options.unicode_identifiers {
+            true => c == '_' || UnicodeXID::is_xid_start(c),
+            false => c == '_' || c.is_ascii_alphabetic(),
         }
     }
 
-    fn peek_is_alphanumeric(&self) -> bool {
+    fn peek_is_ident_continue(&self) -> bool {
         match self.peek() {
-            Some(c) => c.is_alphanumeric(),
+            Some(c) if self.options.unicode_identifiers => UnicodeXID::is_xid_continue(c),
+            Some(c) => c == '_' || c.is_ascii_alphanumeric(),
             None => false,
         }
     }
@@ -121,69 +291,264 @@ impl Lexer {
         self.current >= self.src.len()
     }
 
-    fn skip_whitespace(&mut self) {
+    fn begin_token(&mut self) {
+        self.start = self.current;
+        self.start_line = self.line;
+        self.start_column = self.column;
+    }
+
+    // Skips whitespace and comments. Doc comments and unterminated block
+    // comments can't be skipped silently, so they come back as a token.
+    fn skip_whitespace(&mut self) -> Option<Token<'src>> {
         loop {
-            match self.peek() {
-                Some(' ') | Some('\r') | Some('\t') => {
+            match (self.peek(), self.peek_next()) {
+                (Some(' ') | Some('\r') | Some('\t'), _) => {
                     self.next_char();
                 }
-                Some('\n') => {
-                    self.line += 1;
+                (Some('\n'), _) => {
                     self.next_char();
+                    self.newline();
                 }
-                Some('/') if self.peek_matches_next('\n') => {
+                (Some('/'), Some('/')) => {
+                    self.begin_token();
                     while !self.is_at_end() && self.peek() != Some('\n') {
                         self.next_char();
                     }
+
+                    // `///` starts a doc comment but `////` is a plain comment
+                    let comment = &self.src[self.start..self.current];
+                    if comment.starts_with("///") && !comment.starts_with("////") {
+                        return Some(self.make_token(TokenKind::DocComment));
+                    }
                 }
+                (Some('/'), Some('*')) => {
+                    self.begin_token();
+                    if let Some(error) = self.block_comment() {
+                        return Some(error);
+                    }
+                }
+
+                _ => return None,
+            }
+        }
+    }
 
-                _ => break,
+    // Block comments nest, so `/* /* */ */` is a single comment
+    fn block_comment(&mut self) -> Option<Token<'src>> {
+        let mut depth = 0;
+        loop {
+            match (self.peek(), self.peek_next()) {
+                (Some('/'), Some('*')) => {
+                    self.next_char();
+                    self.next_char();
+                    depth += 1;
+                }
+                (Some('*'), Some('/')) => {
+                    self.next_char();
+                    self.next_char();
+                    depth -= 1;
+                    if depth == 0 {
+                        return None;
+                    }
+                }
+                (Some('\n'), _) => {
+                    self.next_char();
+                    self.newline();
+                }
+                (Some(_), _) => {
+                    self.next_char();
+                }
+                (None, _) => return Some(self.error_token("Unterminated block comment.")),
             }
         }
     }
 
-    fn number(&mut self) -> Token {
-        while self.peek_is_digit() {
+    // consumes digits of the given radix, plus `_` separators when extended
+    // number literals are enabled
+    fn digits(&mut self, radix: u32) {
+        while let Some(c) = self.peek() {
+            let separator = self.options.extended_numbers && c == '_';
+            if !c.is_digit(radix) && !separator {
+                break;
+            }
             self.next_char();
         }
+    }
 
-        if self.peek_matches_next('.') {
+    fn number(&mut self, first: char) -> Token<'src> {
+        if self.options.extended_numbers && first == '0' {
+            match self.peek() {
+                Some('x') | Some('X') => return self.radix_number(16),
+                Some('b') | Some('B') => return self.radix_number(2),
+                _ => {}
+            }
+        }
+
+        self.digits(10);
+
+        // a dot with no digits after it isn't part of the number, so `1.` is a
+        // number followed by a dot
+        if self.peek_matches_next('.') && self.peek_next().is_some_and(|c| c.is_ascii_digit()) {
             self.next_char();
-            while self.peek_is_digit() {
+            self.digits(10);
+        }
+
+        if self.options.extended_numbers && matches!(self.peek(), Some('e') | Some('E')) {
+            let exponent_digit = match self.peek_next() {
+                Some('+') | Some('-') => self.peek_nth(2),
+                c => c,
+            };
+            if exponent_digit.is_some_and(|c| c.is_ascii_digit()) {
                 self.next_char();
+                if matches!(self.peek(), Some('+') | Some('-')) {
+                    self.next_char();
+                }
+                self.digits(10);
             }
         }
-        let num: f64 = self.src[self.start..self.current].parse().unwrap();
 
-        self.make_token(TokenKind::Number(num))
+        let literal = &self.src[self.start..self.current];
+        if !valid_separators(literal, 10) {
+            return self.error_token("Digit separators must be between two digits.");
+        }
+        let literal: String = literal.chars().filter(|c| *c != '_').collect();
+        match literal.parse() {
+            Ok(num) => self.make_token(TokenKind::Number(num)),
+            Err(_) => self.error_token("Invalid number literal."),
+        }
     }
 
-    fn make_token(&self, kind: TokenKind) -> Token {
+    fn radix_number(&mut self, radix: u32) -> Token<'src> {
+        // skip the x or b after the leading 0
+        self.next_char();
+        self.digits(radix);
+
+        let digits = &self.src[self.start + 2..self.current];
+        if !valid_separators(digits, radix) {
+            return self.error_token("Digit separators must be between two digits.");
+        }
+        let digits: String = digits.chars().filter(|c| *c != '_').collect();
+        if digits.is_empty() {
+            return self.error_token("Invalid number literal.");
+        }
+        match u64::from_str_radix(&digits, radix) {
+            Ok(num) => self.make_token(TokenKind::Number(num as f64)),
+            Err(_) => self.error_token("Number literal is too large."),
+        }
+    }
+
+    fn make_token(&self, kind: TokenKind) -> Token<'src> {
         Token {
-            kind: kind,
-            lexeme: self.src[self.start..self.current].to_string(),
-            line: self.line,
+            kind,
+            lexeme: &self.src[self.start..self.current],
+            line: self.start_line,
+            column: self.start_column,
+            span: Span::new(self.start, self.current),
         }
     }
 
-    fn string(&mut self) -> Token {
-        while !self.peek_matches_next('"') && !self.is_at_end() {
-            if self.peek_matches_next('\n') {
-                self.line += 1;
+    // Scans string contents up to the closing quote, or up to an
+    // interpolation when those are enabled. Called after the opening quote,
+    // or after the `}` that closed the previous interpolation
+    fn string(&mut self) -> Token<'src> {
+        let content_start = self.current;
+        // the first bad escape, reported once the rest of the string is skipped
+        let mut invalid_escape = None;
+
+        let kind = loop {
+            match self.peek() {
+                None => return self.error_token("Unterminated string."),
+                Some('"') => break TokenKind::String,
+                Some('$') if self.options.string_interpolation && self.peek_next() == Some('{') => {
+                    break TokenKind::Interpolation
+                }
+                Some('\\') => {
+                    let (line, column, start) = (self.line, self.column, self.current);
+                    if let Err(msg) = self.escape() {
+                        let span = Span::new(start, self.current);
+                        invalid_escape.get_or_insert((msg, line, column, span));
+                    }
+                }
+                Some(_) => {
+                    if self.next_char() == Some('\n') {
+                        self.newline();
+                    }
+                }
             }
+        };
+
+        let content = &self.src[content_start..self.current];
+        // step over the closing quote or the `${`
+        self.next_char();
+        if kind == TokenKind::Interpolation {
             self.next_char();
+            self.interpolations.push(0);
+        }
+
+        if let Some((msg, line, column, span)) = invalid_escape {
+            return Token {
+                kind: TokenKind::Error,
+                lexeme: msg,
+                line,
+                column,
+                span,
+            };
         }
-        if self.is_at_end() {
-            return self.error_token("unterminated string");
+        Token {
+            kind,
+            lexeme: content,
+            line: self.start_line,
+            column: self.start_column,
+            span: Span::new(self.start, self.current),
         }
+    }
 
+    // validates the escape sequence starting at the backslash under the cursor
+    fn escape(&mut self) -> Result<(), &'static str> {
         self.next_char();
-        let string = self.src[self.start..self.current].to_string();
-        self.make_token(TokenKind::String(string))
+        match self.peek() {
+            Some('n' | 't' | 'r' | '"' | '\\' | '$') => {
+                self.next_char();
+                Ok(())
+            }
+            Some('u') => {
+                self.next_char();
+                if self.peek() != Some('{') {
+                    return Err("Invalid unicode escape sequence.");
+                }
+                self.next_char();
+
+                let digits_start = self.current;
+                while self.peek().is_some_and(|c| c.is_ascii_hexdigit()) {
+                    self.next_char();
+                }
+                let digits = &self.src[digits_start..self.current];
+                if self.peek() != Some('}') {
+                    return Err("Invalid unicode escape sequence.");
+                }
+                self.next_char();
+
+                let valid = digits.len() <= 6
+                    && u32::from_str_radix(digits, 16)
+                        .ok()
+                        .and_then(char::from_u32)
+                        .is_some();
+                match valid {
+                    true => Ok(()),
+                    false => Err("Invalid unicode escape sequence."),
+                }
+            }
+            // take the bad char along so the error covers the whole escape
+            Some(c) if c != '\n' => {
+                self.next_char();
+                Err("Invalid escape sequence.")
+            }
+            _ => Err("Invalid escape sequence."),
+        }
     }
 
-    fn identifier(&mut self) -> Token {
-        while self.peek_is_alphanumeric() {
+    fn identifier(&mut self) -> Token<'src> {
+        while self.peek_is_ident_continue() {
             self.next_char();
         }
 
@@ -205,27 +570,31 @@ impl Lexer {
             "true" => self.make_token(TokenKind::True),
             "var" => self.make_token(TokenKind::Var),
             "while" => self.make_token(TokenKind::While),
-            _ => self.make_token(TokenKind::Ident(ident_or_keyword.to_string())),
+            _ => self.make_token(TokenKind::Ident(Symbol::intern(ident_or_keyword))),
         }
     }
 
-    fn error_token(&mut self, msg: &str) -> Token {
+    fn error_token(&mut self, msg: &'static str) -> Token<'src> {
         Token {
             kind: TokenKind::Error,
-            lexeme: msg.to_string(),
-            line: self.line,
+            lexeme: msg,
+            line: self.start_line,
+            column: self.start_column,
+            span: Span::new(self.start, self.current),
         }
     }
 
-    fn make_eof(&self) -> Token {
+    fn make_eof(&self) -> Token<'src> {
         Token {
             kind: TokenKind::Eof,
-            lexeme: "\0".to_string(),
+            lexeme: "",
             line: self.line,
+            column: self.column,
+            span: Span::new(self.current, self.current),
         }
     }
 
-    fn token_matches(&mut self, first: TokenKind, second: TokenKind) -> Token {
+    fn token_matches(&mut self, first: TokenKind, second: TokenKind) -> Token<'src> {
         if self.peek() == Some('=') {
             self.next_char();
             return self.make_token(first);
@@ -233,19 +602,40 @@ impl Lexer {
         self.make_token(second)
     }
 
-    pub fn next_token(&mut self) -> Token {
-        self.skip_whitespace();
-        self.start = self.current;
+    pub fn next_token(&mut self) -> Token<'src> {
+        if let Some(token) = self.skip_whitespace() {
+            return token;
+        }
+        self.begin_token();
+        if let Some(token) = self.registered_operator() {
+            return token;
+        }
         let c = self.next_char();
         match c {
             Some(c) => match c {
-                c if c.is_alphabetic() => self.identifier(),
-                c if c.is_digit(10) => self.number(),
+                c if self.is_ident_start(c) => self.identifier(),
+                c if c.is_ascii_digit() => self.number(c),
 
                 '(' => self.make_token(TokenKind::LPar),
                 ')' => self.make_token(TokenKind::Rpar),
-                '{' => self.make_token(TokenKind::LBrace),
-                '}' => self.make_token(TokenKind::RBrace),
+                '{' => {
+                    if let Some(depth) = self.interpolations.last_mut() {
+                        *depth += 1;
+                    }
+                    self.make_token(TokenKind::LBrace)
+                }
+                '}' => match self.interpolations.last_mut() {
+                    // this brace closes the interpolation, carry on with the string
+                    Some(0) => {
+                        self.interpolations.pop();
+                        self.string()
+                    }
+                    Some(depth) => {
+                        *depth -= 1;
+                        self.make_token(TokenKind::RBrace)
+                    }
+                    None => self.make_token(TokenKind::RBrace),
+                },
                 ';' => self.make_token(TokenKind::Semicolon),
                 ',' => self.make_token(TokenKind::Comma),
                 '.' => self.make_token(TokenKind::Dot),
@@ -260,10 +650,29 @@ impl Lexer {
                 '<' => self.token_matches(TokenKind::LessEqual, TokenKind::Less),
 
                 '"' => self.string(),
-                _ => self.error_token(&format!("Unexpected character {}", c)),
+                _ => self.error_token("Unexpected character."),
             },
 
+            // the strings around an interpolation are unterminated too if
+            // it is, but one error is enough
+            None if !self.interpolations.is_empty() => {
+                self.interpolations.clear();
+                self.error_token("Unterminated string interpolation.")
+            }
             None => self.make_eof(),
         }
     }
 }
+
+// Yields every token up to, but not including, the end of input
+impl<'src> Iterator for Lexer<'src> {
+    type Item = Token<'src>;
+
+    fn next(&mut self) -> Option<Token<'src>> {
+        let token = self.next_token();
+        match token.kind {
+            TokenKind::Eof => None,
+            _ => Some(token),
+        }
+    }
+}
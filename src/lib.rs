@@ -1,4 +1,20 @@
 pub mod ast;
+pub mod formatter;
+pub mod infer;
+pub mod interpreter;
 pub mod jit;
 pub mod lexer;
+pub mod limits;
+#[cfg(feature = "lsp")]
+pub mod lsp;
+pub mod object;
+pub mod optimizer;
 pub mod parser;
+pub mod printer;
+pub mod repl;
+pub mod resolver;
+pub mod sexpr;
+pub mod source;
+pub mod symbol;
+pub mod tier;
+pub mod visit;
@@ -0,0 +1,127 @@
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use crate::interpreter::RuntimeError;
+
+// Native stack left free below the deepest Lox call or parser recursion, for
+// the frames between two checks and for the host once the error has unwound. A Lox call takes
+// about 12 KiB of stack in debug builds and 6 KiB in release builds, so a
+// thread with the usual 8 MiB main stack fits over 600 calls either way.
+pub const STACK_RESERVE: usize = 128 * 1024;
+
+// Bounds on a single run of untrusted code. Every call to
+// Interpreter::execute or Interpreter::evaluate is a run of its own.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Limits {
+    // loop iterations and calls a run may make
+    pub fuel: Option<u64>,
+    // wall clock time a run may take, checked on the same loop iterations
+    // and calls that use up fuel
+    pub timeout: Option<Duration>,
+    // bytes of strings, instances, closures and scopes a run may allocate.
+    // Values are reference counted without a collector to ask what's still
+    // live, so this counts every allocation, freed or not.
+    pub max_allocated: Option<usize>,
+    // Lox calls that may be in progress at once
+    pub max_call_depth: Option<usize>,
+    // bytes of native stack a run may grow by. Every Lox call recurses in
+    // the interpreter, and whatever this is set to, a call that would leave
+    // less than STACK_RESERVE of the thread's stack is a stack overflow
+    // rather than a crash.
+    pub max_stack: Option<usize>,
+}
+
+// roughly where the stack pointer is, which is all the guard needs
+#[inline(never)]
+fn stack_position() -> usize {
+    let marker = 0u8;
+    black_box(&marker) as *const u8 as usize
+}
+
+// whether the thread's own stack is down to STACK_RESERVE, on platforms that
+// say where it ends. Lox calls and the parser's recursion both stop here.
+pub fn stack_exhausted() -> bool {
+    stacker::remaining_stack().is_some_and(|left| left < STACK_RESERVE)
+}
+
+// What the current run has used up of its limits
+#[derive(Debug, Default)]
+pub struct Meter {
+    limits: Limits,
+    fuel: u64,
+    allocated: usize,
+    call_depth: usize,
+    // stack position when the run started
+    stack_base: usize,
+    deadline: Option<Instant>,
+}
+
+impl Meter {
+    pub fn new(limits: Limits) -> Meter {
+        Meter {
+            limits,
+            ..Meter::default()
+        }
+    }
+
+    pub fn start(&mut self) {
+        *self = Meter::new(self.limits);
+        self.deadline = self.limits.timeout.map(|timeout| Instant::now() + timeout);
+        self.stack_base = stack_position();
+    }
+
+    // called on every loop iteration and call, the places a run can spend
+    // an unbounded amount of time in
+    pub fn tick(&mut self) -> Result<(), RuntimeError> {
+        self.fuel += 1;
+        if self.limits.fuel.is_some_and(|fuel| self.fuel > fuel) {
+            return Err(RuntimeError::OutOfFuel);
+        }
+        if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            return Err(RuntimeError::Timeout);
+        }
+        Ok(())
+    }
+
+    pub fn allocate(&mut self, bytes: usize) -> Result<(), RuntimeError> {
+        self.allocated = self.allocated.saturating_add(bytes);
+        match self.limits.max_allocated {
+            Some(max_allocated) if self.allocated > max_allocated => Err(RuntimeError::OutOfMemory),
+            _ => Ok(()),
+        }
+    }
+
+    // the prologue of every call, the same error as clox gives when it runs
+    // out of frames
+    pub fn enter_call(&mut self) -> Result<(), RuntimeError> {
+        if self
+            .limits
+            .max_call_depth
+            .is_some_and(|depth| self.call_depth >= depth)
+        {
+            return Err(RuntimeError::StackOverflow);
+        }
+        if stack_exhausted() {
+            return Err(RuntimeError::StackOverflow);
+        }
+        // stacks grow down on everything we run on, abs_diff just avoids
+        // assuming it
+        let stack = self.stack_base.abs_diff(stack_position());
+        if self
+            .limits
+            .max_stack
+            .is_some_and(|max_stack| stack > max_stack)
+        {
+            return Err(RuntimeError::StackOverflow);
+        }
+        self.call_depth += 1;
+        Ok(())
+    }
+
+    pub fn exit_call(&mut self) {
+        self.call_depth -= 1;
+    }
+}
@@ -0,0 +1,744 @@
+use std::collections::HashMap;
+use std::error::Error;
+
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument,
+    Notification as NotificationTrait, PublishDiagnostics,
+};
+use lsp_types::request::{
+    DocumentSymbolRequest, GotoDefinition, HoverRequest, Rename, Request as RequestTrait,
+    SemanticTokensFullRequest,
+};
+use lsp_types::{
+    Diagnostic, DiagnosticSeverity, DocumentSymbol, DocumentSymbolParams, DocumentSymbolResponse,
+    GotoDefinitionParams, GotoDefinitionResponse, Hover, HoverContents, HoverParams,
+    HoverProviderCapability, Location, MarkupContent, MarkupKind, OneOf, Position,
+    PublishDiagnosticsParams, Range, RenameParams, SemanticToken, SemanticTokenModifier,
+    SemanticTokenType, SemanticTokens, SemanticTokensFullOptions, SemanticTokensLegend,
+    SemanticTokensOptions, SemanticTokensParams, SemanticTokensResult,
+    SemanticTokensServerCapabilities, ServerCapabilities, SymbolKind, TextDocumentSyncCapability,
+    TextDocumentSyncKind, TextEdit, Url, WorkspaceEdit,
+};
+
+use crate::ast::{Function, Statement, Variable};
+use crate::lexer::{Lexer, TokenKind};
+use crate::parser::{ParseError, Parser};
+use crate::resolver::Resolver;
+use crate::source::{self, ColumnUnit, SourceFile, Span};
+use crate::symbol::Symbol;
+use crate::visit::{walk_statement, walk_statements, Visitor};
+
+// Language server behind `blox lsp`. Every open document is lexed, parsed,
+// resolved and bound again on each change, which Lox programs are small
+// enough for, and requests are answered from that last analysis.
+
+const TOKEN_TYPES: [SemanticTokenType; 11] = [
+    SemanticTokenType::KEYWORD,
+    SemanticTokenType::VARIABLE,
+    SemanticTokenType::PARAMETER,
+    SemanticTokenType::FUNCTION,
+    SemanticTokenType::METHOD,
+    SemanticTokenType::CLASS,
+    SemanticTokenType::PROPERTY,
+    SemanticTokenType::STRING,
+    SemanticTokenType::NUMBER,
+    SemanticTokenType::OPERATOR,
+    SemanticTokenType::COMMENT,
+];
+
+// bit 0 of the modifier set
+const DECLARATION: u32 = 1;
+
+fn token_type(token_type: SemanticTokenType) -> u32 {
+    TOKEN_TYPES.iter().position(|t| *t == token_type).unwrap() as u32
+}
+
+fn capabilities() -> ServerCapabilities {
+    ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        semantic_tokens_provider: Some(SemanticTokensServerCapabilities::SemanticTokensOptions(
+            SemanticTokensOptions {
+                legend: SemanticTokensLegend {
+                    token_types: TOKEN_TYPES.to_vec(),
+                    token_modifiers: vec![SemanticTokenModifier::DECLARATION],
+                },
+                full: Some(SemanticTokensFullOptions::Bool(true)),
+                ..SemanticTokensOptions::default()
+            },
+        )),
+        definition_provider: Some(OneOf::Left(true)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        rename_provider: Some(OneOf::Left(true)),
+        ..ServerCapabilities::default()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Variable,
+    Parameter,
+    Function,
+    Class,
+    Method,
+    // the implicit `this` of a class's methods, defined at the class name
+    This,
+}
+
+struct Definition {
+    span: Span,
+    kind: Kind,
+    // what hover shows, e.g. `fun add(a, b)`
+    detail: String,
+}
+
+fn signature(function: &Function) -> String {
+    let params: Vec<&str> = function.params.iter().map(|p| p.name.as_str()).collect();
+    format!("{}({})", function.name.name, params.join(", "))
+}
+
+// Links every use of a name to the place it was defined. Scopes follow the
+// resolver's: one per block, one for a function's parameters and body and
+// one holding `this` around each method. Names not found in any scope are
+// globals, looked up once the whole program has been seen since a function
+// may use a global declared after it.
+#[derive(Default)]
+struct Binder {
+    definitions: Vec<Definition>,
+    references: Vec<(Span, usize)>,
+    scopes: Vec<Vec<(Symbol, usize)>>,
+    globals: HashMap<Symbol, usize>,
+    unresolved: Vec<(Span, Symbol)>,
+}
+
+impl Binder {
+    fn add(&mut self, span: Span, kind: Kind, detail: String) -> usize {
+        self.definitions.push(Definition { span, kind, detail });
+        self.definitions.len() - 1
+    }
+
+    fn declare(&mut self, variable: &Variable, kind: Kind, detail: String) -> usize {
+        match self.scopes.last() {
+            // redeclaring a global assigns to the existing one
+            None => match self.globals.get(&variable.name) {
+                Some(&id) => {
+                    self.references.push((variable.span, id));
+                    id
+                }
+                None => {
+                    let id = self.add(variable.span, kind, detail);
+                    self.globals.insert(variable.name.clone(), id);
+                    id
+                }
+            },
+            Some(_) => {
+                let id = self.add(variable.span, kind, detail);
+                self.scopes
+                    .last_mut()
+                    .unwrap()
+                    .push((variable.name.clone(), id));
+                id
+            }
+        }
+    }
+
+    fn finish(mut self) -> (Vec<Definition>, Vec<(Span, usize)>) {
+        for (span, name) in std::mem::take(&mut self.unresolved) {
+            if let Some(&id) = self.globals.get(&name) {
+                self.references.push((span, id));
+            }
+        }
+        (self.definitions, self.references)
+    }
+}
+
+impl Visitor for Binder {
+    fn visit_statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Var(name, initializer) => {
+                if let Some(initializer) = initializer {
+                    self.visit_expression(initializer);
+                }
+                self.declare(name, Kind::Variable, format!("var {}", name.name));
+            }
+            Statement::Block(statements) => {
+                self.scopes.push(Vec::new());
+                walk_statements(self, statements);
+                self.scopes.pop();
+            }
+            Statement::Function(function) => {
+                let detail = format!("fun {}", signature(function));
+                self.declare(&function.name, Kind::Function, detail);
+                self.visit_function(function);
+            }
+            Statement::Class(name, methods) => {
+                self.declare(name, Kind::Class, format!("class {}", name.name));
+                let this = self.add(
+                    name.span,
+                    Kind::This,
+                    format!("this: {} instance", name.name),
+                );
+                for method in methods {
+                    let detail = format!("method {}.{}", name.name, signature(method));
+                    self.add(method.name.span, Kind::Method, detail);
+                    self.scopes.push(vec![(Symbol::intern("this"), this)]);
+                    self.visit_function(method);
+                    self.scopes.pop();
+                }
+            }
+            _ => walk_statement(self, statement),
+        }
+    }
+
+    fn visit_function(&mut self, function: &Function) {
+        self.scopes.push(Vec::new());
+        for param in &function.params {
+            self.declare(param, Kind::Parameter, format!("parameter {}", param.name));
+        }
+        walk_statements(self, &function.body);
+        self.scopes.pop();
+    }
+
+    fn visit_variable(&mut self, variable: &Variable) {
+        let local = self.scopes.iter().rev().find_map(|scope| {
+            scope
+                .iter()
+                .rev()
+                .find(|(name, _)| *name == variable.name)
+                .map(|(_, id)| *id)
+        });
+        match local {
+            Some(id) => self.references.push((variable.span, id)),
+            None => self.unresolved.push((variable.span, variable.name.clone())),
+        }
+    }
+}
+
+fn parse_error_message(error: &ParseError) -> String {
+    match error {
+        ParseError::Expected(msg, _) => msg.to_string(),
+        ParseError::UnexpectedError("prefix") => "Expect expression.".to_string(),
+        ParseError::UnexpectedError(what) => format!("Unexpected {}.", what),
+        ParseError::Lexical(msg, _) => msg.clone(),
+    }
+}
+
+// Everything the server knows about one version of a document
+struct Analysis {
+    file: SourceFile,
+    version: i32,
+    diagnostics: Vec<(Span, String)>,
+    // whatever parsed, statements with syntax errors are left out
+    statements: Vec<Statement>,
+    definitions: Vec<Definition>,
+    references: Vec<(Span, usize)>,
+}
+
+impl Analysis {
+    fn new(src: &str, version: i32) -> Analysis {
+        let mut diagnostics = Vec::new();
+        let (mut statements, errors) = Parser::new(src).parse_program_recovering();
+        for (error, span) in errors {
+            diagnostics.push((span, parse_error_message(&error)));
+        }
+
+        if let Err(errors) = Resolver::default().resolve(&mut statements) {
+            for error in errors {
+                // errors not about a name only carry a line and lexeme, so
+                // point at the first token on that line spelled the same
+                let span = match error.span.is_empty() {
+                    false => error.span,
+                    true => Lexer::new(src)
+                        .find(|t| t.line == error.line && t.lexeme == error.at)
+                        .map_or(Span::default(), |t| t.span),
+                };
+                diagnostics.push((span, error.message.to_string()));
+            }
+        }
+
+        let mut binder = Binder::default();
+        walk_statements(&mut binder, &statements);
+        let (definitions, references) = binder.finish();
+
+        Analysis {
+            file: SourceFile::new("", src),
+            version,
+            diagnostics,
+            statements,
+            definitions,
+            references,
+        }
+    }
+
+    fn position(&self, offset: usize) -> Position {
+        let position = self.file.position(offset, ColumnUnit::Utf16);
+        Position::new(position.line as u32 - 1, position.column as u32 - 1)
+    }
+
+    fn range(&self, span: Span) -> Range {
+        Range::new(self.position(span.start), self.position(span.end))
+    }
+
+    fn offset(&self, position: Position) -> Option<usize> {
+        let position = source::Position {
+            line: position.line as u64 + 1,
+            column: position.character as u64 + 1,
+        };
+        self.file.offset(position, ColumnUnit::Utf16)
+    }
+
+    // the name under the cursor and the definition it refers to, a cursor
+    // right after a name still counts as on it
+    fn definition_at(&self, position: Position) -> Option<(Span, usize)> {
+        let offset = self.offset(position)?;
+        let contains = |span: &Span| span.start <= offset && offset <= span.end;
+
+        self.references
+            .iter()
+            .find(|(span, _)| contains(span))
+            .copied()
+            .or_else(|| {
+                self.definitions
+                    .iter()
+                    .position(|d| d.kind != Kind::This && contains(&d.span))
+                    .map(|id| (self.definitions[id].span, id))
+            })
+    }
+
+    // Whether other source binds every name the same way, matching
+    // definitions and references up in the order they were found
+    fn binds_like(&self, other: &Analysis) -> bool {
+        self.definitions.len() == other.definitions.len()
+            && self.diagnostics.len() == other.diagnostics.len()
+            && self
+                .references
+                .iter()
+                .map(|(_, id)| id)
+                .eq(other.references.iter().map(|(_, id)| id))
+    }
+
+    // every span naming the definition, the definition itself first
+    fn occurrences(&self, id: usize) -> Vec<Span> {
+        std::iter::once(self.definitions[id].span)
+            .chain(
+                self.references
+                    .iter()
+                    .filter(|(_, other)| *other == id)
+                    .map(|(span, _)| *span),
+            )
+            .collect()
+    }
+
+    fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.diagnostics
+            .iter()
+            .map(|(span, message)| Diagnostic {
+                range: self.range(*span),
+                severity: Some(DiagnosticSeverity::ERROR),
+                source: Some("blox".to_string()),
+                message: message.clone(),
+                ..Diagnostic::default()
+            })
+            .collect()
+    }
+
+    fn semantic_tokens(&self) -> Vec<SemanticToken> {
+        // identifiers the binder knows about, keyed by where they start
+        let mut names = HashMap::new();
+        for (span, id) in &self.references {
+            names.insert(span.start, (self.definitions[*id].kind, 0));
+        }
+        for definition in &self.definitions {
+            if definition.kind != Kind::This {
+                names.insert(definition.span.start, (definition.kind, DECLARATION));
+            }
+        }
+
+        let src = self.file.src();
+        let mut data = Vec::new();
+        let mut last = Position::new(0, 0);
+        let mut previous = None;
+        for token in Lexer::new(src) {
+            let classified = match token.kind {
+                TokenKind::Ident(_) => Some(match names.get(&token.span.start) {
+                    Some((kind, modifiers)) => {
+                        let ty = match kind {
+                            Kind::Variable => SemanticTokenType::VARIABLE,
+                            Kind::Parameter => SemanticTokenType::PARAMETER,
+                            Kind::Function => SemanticTokenType::FUNCTION,
+                            Kind::Class => SemanticTokenType::CLASS,
+                            Kind::Method => SemanticTokenType::METHOD,
+                            Kind::This => SemanticTokenType::KEYWORD,
+                        };
+                        (ty, *modifiers)
+                    }
+                    None if previous == Some(TokenKind::Dot) => (SemanticTokenType::PROPERTY, 0),
+                    None => (SemanticTokenType::VARIABLE, 0),
+                }),
+                TokenKind::And
+                | TokenKind::Class
+                | TokenKind::Else
+                | TokenKind::False
+                | TokenKind::For
+                | TokenKind::Fun
+                | TokenKind::If
+                | TokenKind::Nil
+                | TokenKind::Or
+                | TokenKind::Print
+                | TokenKind::Return
+                | TokenKind::Super
+                | TokenKind::This
+                | TokenKind::True
+                | TokenKind::Var
+                | TokenKind::While => Some((SemanticTokenType::KEYWORD, 0)),
+                TokenKind::String | TokenKind::Interpolation => {
+                    Some((SemanticTokenType::STRING, 0))
+                }
+                TokenKind::Number(_) => Some((SemanticTokenType::NUMBER, 0)),
+                TokenKind::Minus
+                | TokenKind::Plus
+                | TokenKind::Star
+                | TokenKind::Slash
+                | TokenKind::Bang
+                | TokenKind::NotBang
+                | TokenKind::Equal
+                | TokenKind::IsEqual
+                | TokenKind::Greater
+                | TokenKind::GreaterEqual
+                | TokenKind::Less
+                | TokenKind::LessEqual
+                | TokenKind::Operator(_) => Some((SemanticTokenType::OPERATOR, 0)),
+                TokenKind::DocComment => Some((SemanticTokenType::COMMENT, 0)),
+                _ => None,
+            };
+            previous = Some(token.kind.clone());
+
+            let (ty, modifiers) = match classified {
+                Some(classified) => classified,
+                None => continue,
+            };
+            // clients don't have to support tokens spanning lines, so
+            // multi-line strings and comments are sent one line at a time
+            let mut start = token.span.start;
+            for line in src[token.span.start..token.span.end].split('\n') {
+                let text = line.trim_end_matches('\r');
+                if !text.is_empty() {
+                    let position = self.position(start);
+                    let delta_line = position.line - last.line;
+                    data.push(SemanticToken {
+                        delta_line,
+                        delta_start: match delta_line {
+                            0 => position.character - last.character,
+                            _ => position.character,
+                        },
+                        length: text.encode_utf16().count() as u32,
+                        token_type: token_type(ty.clone()),
+                        token_modifiers_bitset: modifiers,
+                    });
+                    last = position;
+                }
+                start += line.len() + 1;
+            }
+        }
+        data
+    }
+
+    #[allow(deprecated)]
+    fn symbol(
+        &self,
+        variable: &Variable,
+        kind: SymbolKind,
+        detail: Option<String>,
+    ) -> DocumentSymbol {
+        DocumentSymbol {
+            name: variable.name.to_string(),
+            detail,
+            kind,
+            tags: None,
+            deprecated: None,
+            range: self.range(variable.span),
+            selection_range: self.range(variable.span),
+            children: None,
+        }
+    }
+
+    fn document_symbols(&self) -> Vec<DocumentSymbol> {
+        self.statements
+            .iter()
+            .filter_map(|statement| match statement {
+                Statement::Var(name, _) => Some(self.symbol(name, SymbolKind::VARIABLE, None)),
+                Statement::Function(function) => Some(self.symbol(
+                    &function.name,
+                    SymbolKind::FUNCTION,
+                    Some(signature(function)),
+                )),
+                Statement::Class(name, methods) => {
+                    let methods = methods
+                        .iter()
+                        .map(|method| {
+                            self.symbol(&method.name, SymbolKind::METHOD, Some(signature(method)))
+                        })
+                        .collect();
+                    Some(DocumentSymbol {
+                        children: Some(methods),
+                        ..self.symbol(name, SymbolKind::CLASS, None)
+                    })
+                }
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+// whether a rename target is a plain identifier, not a keyword or anything
+// with whitespace or comments around it
+fn is_identifier(name: &str) -> bool {
+    let mut tokens = Lexer::new(name);
+    match (tokens.next(), tokens.next()) {
+        (Some(token), None) => matches!(token.kind, TokenKind::Ident(_)) && token.lexeme == name,
+        _ => false,
+    }
+}
+
+type Handler<'a, R> =
+    fn(&Server<'a>, <R as RequestTrait>::Params) -> Result<<R as RequestTrait>::Result, String>;
+
+struct Server<'a> {
+    connection: &'a Connection,
+    documents: HashMap<Url, Analysis>,
+}
+
+impl<'a> Server<'a> {
+    fn document(&self, uri: &Url) -> Result<&Analysis, String> {
+        self.documents
+            .get(uri)
+            .ok_or_else(|| format!("{} is not open", uri))
+    }
+
+    fn handle<R: RequestTrait>(&self, request: Request, handler: Handler<'a, R>) -> Response {
+        let id = request.id.clone();
+        match request.extract::<R::Params>(R::METHOD) {
+            Ok((id, params)) => match handler(self, params) {
+                Ok(result) => Response::new_ok(id, result),
+                Err(message) => Response::new_err(id, ErrorCode::RequestFailed as i32, message),
+            },
+            Err(error) => Response::new_err(id, ErrorCode::InvalidParams as i32, error.to_string()),
+        }
+    }
+
+    fn request(&self, request: Request) -> Response {
+        match request.method.as_str() {
+            GotoDefinition::METHOD => self.handle::<GotoDefinition>(request, Server::definition),
+            HoverRequest::METHOD => self.handle::<HoverRequest>(request, Server::hover),
+            DocumentSymbolRequest::METHOD => {
+                self.handle::<DocumentSymbolRequest>(request, Server::document_symbols)
+            }
+            Rename::METHOD => self.handle::<Rename>(request, Server::rename),
+            SemanticTokensFullRequest::METHOD => {
+                self.handle::<SemanticTokensFullRequest>(request, Server::semantic_tokens)
+            }
+            method => Response::new_err(
+                request.id.clone(),
+                ErrorCode::MethodNotFound as i32,
+                format!("unhandled request {}", method),
+            ),
+        }
+    }
+
+    fn definition(
+        &self,
+        params: GotoDefinitionParams,
+    ) -> Result<Option<GotoDefinitionResponse>, String> {
+        let params = params.text_document_position_params;
+        let document = self.document(&params.text_document.uri)?;
+        Ok(document.definition_at(params.position).map(|(_, id)| {
+            let range = document.range(document.definitions[id].span);
+            GotoDefinitionResponse::Scalar(Location::new(params.text_document.uri, range))
+        }))
+    }
+
+    fn hover(&self, params: HoverParams) -> Result<Option<Hover>, String> {
+        let params = params.text_document_position_params;
+        let document = self.document(&params.text_document.uri)?;
+        Ok(document
+            .definition_at(params.position)
+            .map(|(span, id)| Hover {
+                contents: HoverContents::Markup(MarkupContent {
+                    kind: MarkupKind::Markdown,
+                    value: format!("```lox\n{}\n```", document.definitions[id].detail),
+                }),
+                range: Some(document.range(span)),
+            }))
+    }
+
+    fn document_symbols(
+        &self,
+        params: DocumentSymbolParams,
+    ) -> Result<Option<DocumentSymbolResponse>, String> {
+        let document = self.document(&params.text_document.uri)?;
+        Ok(Some(DocumentSymbolResponse::Nested(
+            document.document_symbols(),
+        )))
+    }
+
+    fn rename(&self, params: RenameParams) -> Result<Option<WorkspaceEdit>, String> {
+        let position = params.text_document_position;
+        let document = self.document(&position.text_document.uri)?;
+        if !is_identifier(&params.new_name) {
+            return Err(format!("'{}' is not a valid name.", params.new_name));
+        }
+
+        let id = match document.definition_at(position.position) {
+            Some((_, id)) => id,
+            None => return Ok(None),
+        };
+        match document.definitions[id].kind {
+            Kind::This => return Err("Can't rename 'this'.".to_string()),
+            // `a.m()` could call any class's m, so calls aren't linked to
+            // the methods and renaming only the declaration would break them
+            Kind::Method => return Err("Can't rename methods.".to_string()),
+            _ => {}
+        }
+
+        // the renamed program has to bind every name the same way, which
+        // rules out clashing with a name in the same scope or capturing a
+        // use of an outer one
+        let mut occurrences = document.occurrences(id);
+        occurrences.sort_by_key(|span| span.start);
+        let src = document.file.src();
+        let mut renamed = String::new();
+        let mut end = 0;
+        for span in &occurrences {
+            renamed.push_str(&src[end..span.start]);
+            renamed.push_str(&params.new_name);
+            end = span.end;
+        }
+        renamed.push_str(&src[end..]);
+        if !document.binds_like(&Analysis::new(&renamed, document.version)) {
+            return Err(format!(
+                "'{}' would clash with another name.",
+                params.new_name
+            ));
+        }
+
+        let edits = occurrences
+            .into_iter()
+            .map(|span| TextEdit::new(document.range(span), params.new_name.clone()))
+            .collect();
+        let changes = HashMap::from([(position.text_document.uri, edits)]);
+        Ok(Some(WorkspaceEdit::new(changes)))
+    }
+
+    fn semantic_tokens(
+        &self,
+        params: SemanticTokensParams,
+    ) -> Result<Option<SemanticTokensResult>, String> {
+        let document = self.document(&params.text_document.uri)?;
+        Ok(Some(SemanticTokensResult::Tokens(SemanticTokens {
+            result_id: None,
+            data: document.semantic_tokens(),
+        })))
+    }
+
+    fn notification(
+        &mut self,
+        notification: Notification,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => match params::<DidOpenTextDocument>(notification) {
+                Some(params) => {
+                    let document = params.text_document;
+                    self.open(document.uri, &document.text, document.version)
+                }
+                None => Ok(()),
+            },
+            DidChangeTextDocument::METHOD => {
+                match params::<DidChangeTextDocument>(notification) {
+                    // with full sync the last change holds the whole text
+                    Some(mut params) => match params.content_changes.pop() {
+                        Some(change) => self.open(
+                            params.text_document.uri,
+                            &change.text,
+                            params.text_document.version,
+                        ),
+                        None => Ok(()),
+                    },
+                    None => Ok(()),
+                }
+            }
+            DidCloseTextDocument::METHOD => match params::<DidCloseTextDocument>(notification) {
+                Some(params) => {
+                    let uri = params.text_document.uri;
+                    self.documents.remove(&uri);
+                    self.publish(PublishDiagnosticsParams::new(uri, Vec::new(), None))
+                }
+                None => Ok(()),
+            },
+            _ => Ok(()),
+        }
+    }
+
+    fn open(
+        &mut self,
+        uri: Url,
+        text: &str,
+        version: i32,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let analysis = Analysis::new(text, version);
+        let params = PublishDiagnosticsParams::new(
+            uri.clone(),
+            analysis.diagnostics(),
+            Some(analysis.version),
+        );
+        self.documents.insert(uri, analysis);
+        self.publish(params)
+    }
+
+    fn publish(
+        &self,
+        params: PublishDiagnosticsParams,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let notification = Notification::new(PublishDiagnostics::METHOD.to_string(), params);
+        self.connection
+            .sender
+            .send(Message::Notification(notification))?;
+        Ok(())
+    }
+}
+
+// There is no way to answer a notification, so one with params that don't
+// fit its method is logged and otherwise ignored
+fn params<N: NotificationTrait>(notification: Notification) -> Option<N::Params> {
+    match notification.extract::<N::Params>(N::METHOD) {
+        Ok(params) => Some(params),
+        Err(error) => {
+            eprintln!("ignoring {} notification: {}", N::METHOD, error);
+            None
+        }
+    }
+}
+
+// Serves one client over the connection until it asks the server to exit
+pub fn run(connection: Connection) -> Result<(), Box<dyn Error + Send + Sync>> {
+    connection.initialize(serde_json::to_value(capabilities())?)?;
+
+    let mut server = Server {
+        connection: &connection,
+        documents: HashMap::new(),
+    };
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request)? {
+                    return Ok(());
+                }
+                let response = server.request(request);
+                connection.sender.send(Message::Response(response))?;
+            }
+            Message::Notification(notification) => server.notification(notification)?,
+            Message::Response(_) => {}
+        }
+    }
+    Ok(())
+}
@@ -1,27 +1,355 @@
-use std::io::{stdin, stdout, Write};
-use std::{env, process::exit};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+use std::{env, fs, process::exit, thread};
+
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+
+use blox::formatter::format;
+use blox::interpreter::Interpreter;
+use blox::lexer::Lexer;
+use blox::limits::Limits;
+use blox::optimizer::optimize;
+use blox::parser::Parser;
+use blox::repl::{is_incomplete, Session};
+use blox::resolver::Resolver;
+use blox::sexpr::Sexpr;
+use blox::tier::DEFAULT_JIT_THRESHOLD;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Emit {
+    // the program as parsed
+    Ast,
+    // the program after constant folding and dead branch elimination
+    AstOpt,
+    // the parsed program as JSON, needs the serde feature
+    AstJson,
+    // the parsed program as S-expressions
+    Sexpr,
+    // every token in the file as JSON, needs the serde feature
+    TokensJson,
+}
+
+enum Command {
+    Repl,
+    Run(String),
+    // print every token in a file
+    Tokens(String),
+    // reformat files in place, or with check only report the ones that
+    // aren't formatted
+    Fmt { paths: Vec<String>, check: bool },
+    // language server over stdin and stdout, needs the lsp feature
+    Lsp,
+}
+
+struct Options {
+    jit_threshold: u64,
+    // limits and emit only apply when running a file
+    limits: Limits,
+    emit: Option<Emit>,
+    command: Command,
+}
+
+// Every Lox call recurses in the interpreter, so commands run on a thread
+// with room for thousands of calls rather than on the main thread. The
+// memory is only used as deep as a script actually recurses.
+const STACK_SIZE: usize = 64 << 20;
+
+const USAGE: &str =
+    "Usage: blox [--jit-threshold N] [--emit=ast|ast-opt|ast-json|sexpr|tokens-json] [[--] path]
+       blox [--fuel N] [--timeout MS] [--max-heap BYTES] [--max-call-depth N] [--max-stack BYTES] [--] <path>
+       blox tokens <path>
+       blox fmt [--check] <path>...
+       blox lsp
+
+--max-heap counts every byte a run allocates, including what it has freed since";
+
+// the value following a flag like --fuel
+fn flag_value<T: FromStr>(
+    args: &mut impl Iterator<Item = String>,
+    flag: &str,
+) -> Result<T, String> {
+    let value = args
+        .next()
+        .ok_or_else(|| format!("{} expects a number", flag))?;
+    value
+        .parse()
+        .map_err(|_| format!("invalid {} value {}", flag, value))
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options {
+        jit_threshold: DEFAULT_JIT_THRESHOLD,
+        limits: Limits::default(),
+        emit: None,
+        command: Command::Repl,
+    };
+
+    let mut positional = Vec::new();
+    let mut check = false;
+    // whether a `--` came before the positional arguments
+    let mut script = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            // everything after is a script path, even one named like a
+            // subcommand or starting with a dash
+            "--" => {
+                script = true;
+                positional.extend(args.by_ref());
+            }
+            "--jit-threshold" => options.jit_threshold = flag_value(&mut args, &arg)?,
+            "--fuel" => options.limits.fuel = Some(flag_value(&mut args, &arg)?),
+            "--timeout" => {
+                let millis = flag_value(&mut args, &arg)?;
+                options.limits.timeout = Some(Duration::from_millis(millis));
+            }
+            "--max-heap" => options.limits.max_allocated = Some(flag_value(&mut args, &arg)?),
+            "--max-call-depth" => {
+                options.limits.max_call_depth = Some(flag_value(&mut args, &arg)?)
+            }
+            "--max-stack" => options.limits.max_stack = Some(flag_value(&mut args, &arg)?),
+            "--emit=ast" => options.emit = Some(Emit::Ast),
+            "--emit=ast-opt" => options.emit = Some(Emit::AstOpt),
+            "--emit=ast-json" => options.emit = Some(Emit::AstJson),
+            "--emit=sexpr" => options.emit = Some(Emit::Sexpr),
+            "--emit=tokens-json" => options.emit = Some(Emit::TokensJson),
+            _ if arg.starts_with("--emit=") => return Err(format!("unknown emit kind {}", arg)),
+            "--check" => check = true,
+            _ => positional.push(arg),
+        }
+    }
+
+    options.command = match positional.as_slice() {
+        [path] if script => Command::Run(path.clone()),
+        _ if script => return Err(USAGE.to_string()),
+        [command, path] if command == "tokens" => Command::Tokens(path.clone()),
+        [command, paths @ ..] if command == "fmt" && !paths.is_empty() => Command::Fmt {
+            paths: paths.to_vec(),
+            check,
+        },
+        [command] if command == "lsp" => Command::Lsp,
+        // subcommands missing their paths, a script of that name needs `--`
+        [command] if command == "tokens" || command == "fmt" => return Err(USAGE.to_string()),
+        [path] => Command::Run(path.clone()),
+        [] => Command::Repl,
+        _ => return Err(USAGE.to_string()),
+    };
+    if check && !matches!(options.command, Command::Fmt { .. }) {
+        return Err(USAGE.to_string());
+    }
+    // the REPL has :ast and :opt instead
+    if options.emit.is_some() && !matches!(options.command, Command::Run(_)) {
+        return Err("--emit needs a file to run".to_string());
+    }
+    if matches!(options.emit, Some(Emit::AstJson | Emit::TokensJson)) && !cfg!(feature = "serde") {
+        return Err("JSON output needs blox built with --features serde".to_string());
+    }
+    if matches!(options.command, Command::Lsp) && !cfg!(feature = "lsp") {
+        return Err("the language server needs blox built with --features lsp".to_string());
+    }
+    Ok(options)
+}
 
-use blox::jit::JIT;
 fn main() -> Result<(), String> {
-    let args: Vec<String> = env::args().collect();
-    if args.len() == 1 {
-        repl()
-    } else if args.len() == 2 {
-        run_file(&args[1])
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(64)
+        }
+    };
+
+    let command = thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(move || match &options.command {
+            Command::Repl => repl(options.jit_threshold),
+            Command::Run(path) => run_file(path, &options),
+            Command::Tokens(path) => dump_tokens(path),
+            Command::Fmt { paths, check } => format_files(paths, *check),
+            Command::Lsp => serve(),
+        })
+        .map_err(|e| e.to_string())?;
+    match command.join() {
+        Ok(result) => result?,
+        // the panic message has already been printed
+        Err(_) => exit(101),
     }
     exit(0)
 }
 
-fn repl() {
+#[cfg(feature = "lsp")]
+fn serve() -> Result<(), String> {
+    let (connection, io_threads) = lsp_server::Connection::stdio();
+    blox::lsp::run(connection).map_err(|e| e.to_string())?;
+    io_threads.join().map_err(|e| e.to_string())
+}
+
+// unreachable, parse_args already refuses the command
+#[cfg(not(feature = "lsp"))]
+fn serve() -> Result<(), String> {
+    Ok(())
+}
+
+// history is kept in the home directory when there is one
+fn history_path() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(".blox_history"))
+}
+
+fn repl(jit_threshold: u64) -> Result<(), String> {
+    let mut editor = DefaultEditor::new().map_err(|e| e.to_string())?;
+    let history = history_path();
+    if let Some(path) = &history {
+        // there is no history yet the first time
+        let _ = editor.load_history(path);
+    }
+
+    let mut session = Session::new(jit_threshold);
+    let mut input = String::new();
     loop {
-        print!("> ");
-        let mut s = String::new();
-        let _ = stdout().flush();
-        stdin().read_line(&mut s).unwrap();
-        let mut jit = JIT::default();
-        let code = jit.compile(&s).unwrap();
-        println!("{}", code());
+        let prompt = if input.is_empty() { "> " } else { "... " };
+        match editor.readline(prompt) {
+            Ok(line) => {
+                input.push_str(&line);
+                input.push('\n');
+            }
+            // Ctrl-C drops whatever has been typed so far
+            Err(ReadlineError::Interrupted) => {
+                input.clear();
+                continue;
+            }
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e.to_string()),
+        }
+        if is_incomplete(&input) {
+            continue;
+        }
+
+        let _ = editor.add_history_entry(input.trim_end());
+        match session.eval(&input) {
+            Ok(output) if output.is_empty() => {}
+            Ok(output) => println!("{}", output),
+            Err(e) => eprintln!("{}", e),
+        }
+        input.clear();
+    }
+
+    if let Some(path) = &history {
+        let _ = editor.save_history(path);
     }
+    Ok(())
 }
 
-fn run_file(_path: &str) {}
+fn run_file(path: &str, options: &Options) -> Result<(), String> {
+    let emit = options.emit;
+    let src = fs::read_to_string(path).map_err(|e| format!("could not read {}: {}", path, e))?;
+
+    #[cfg(feature = "serde")]
+    if emit == Some(Emit::TokensJson) {
+        print_json(&Lexer::new(&src).collect::<Vec<_>>());
+        return Ok(());
+    }
+
+    let mut program = match Parser::new(&src).parse_program() {
+        Ok(program) => program,
+        Err(e) => {
+            eprintln!("{:?}", e);
+            exit(65)
+        }
+    };
+
+    if let Err(errors) = Resolver::default().resolve(&mut program) {
+        for error in errors {
+            eprintln!("{}", error);
+        }
+        exit(65)
+    }
+
+    match emit {
+        Some(Emit::Ast) => {
+            println!("{:#?}", program);
+            return Ok(());
+        }
+        #[cfg(feature = "serde")]
+        Some(Emit::AstJson) => {
+            print_json(&program);
+            return Ok(());
+        }
+        Some(Emit::Sexpr) => {
+            for statement in &program {
+                println!("{}", Sexpr(statement));
+            }
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    let program = optimize(program);
+    if emit == Some(Emit::AstOpt) {
+        println!("{:#?}", program);
+        return Ok(());
+    }
+
+    let mut interpreter = Interpreter::with_limits(options.limits);
+    interpreter.set_jit_threshold(options.jit_threshold);
+    if let Err(e) = interpreter.execute(&program) {
+        eprintln!("{}", e);
+        exit(70)
+    }
+    Ok(())
+}
+
+#[cfg(feature = "serde")]
+fn print_json<T: serde::Serialize>(value: &T) {
+    let json = serde_json::to_string_pretty(value).expect("the AST and tokens always serialize");
+    println!("{}", json);
+}
+
+fn dump_tokens(path: &str) -> Result<(), String> {
+    let src = fs::read_to_string(path).map_err(|e| format!("could not read {}: {}", path, e))?;
+
+    for token in Lexer::new(&src) {
+        println!(
+            "{}:{} {:?} {:?} {}..{}",
+            token.line, token.column, token.kind, token.lexeme, token.span.start, token.span.end
+        );
+    }
+    Ok(())
+}
+
+// Exits with 65 if a file doesn't parse, and in check mode with 1 if any
+// file isn't formatted
+fn format_files(paths: &[String], check: bool) -> Result<(), String> {
+    let mut invalid = false;
+    let mut unformatted = false;
+    for path in paths {
+        let src =
+            fs::read_to_string(path).map_err(|e| format!("could not read {}: {}", path, e))?;
+        let formatted = match format(&src) {
+            Ok(formatted) => formatted,
+            Err(e) => {
+                eprintln!("{}: {}", path, e);
+                invalid = true;
+                continue;
+            }
+        };
+
+        if formatted == src {
+            continue;
+        }
+        if check {
+            println!("{}", path);
+            unformatted = true;
+        } else {
+            fs::write(path, formatted).map_err(|e| format!("could not write {}: {}", path, e))?;
+        }
+    }
+
+    if invalid {
+        exit(65)
+    }
+    if unformatted {
+        exit(1)
+    }
+    Ok(())
+}
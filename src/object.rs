@@ -0,0 +1,290 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Add;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::ast::Identifier;
+use crate::interpreter::{Class, Closure, Value};
+
+pub type ShapeId = usize;
+
+static NEXT_SHAPE_ID: AtomicUsize = AtomicUsize::new(0);
+
+// A hidden class describing the layout of an instance: which fields it has
+// and the slot each one is stored in. Adding a field moves an instance to a
+// child shape, and transitions are cached so instances that get the same
+// fields in the same order end up sharing a shape.
+#[derive(Debug)]
+pub struct Shape {
+    id: ShapeId,
+    slots: HashMap<Identifier, usize>,
+    transitions: RefCell<HashMap<Identifier, Rc<Shape>>>,
+}
+
+impl Shape {
+    // every class starts its instances off from its own root shape, so a shape
+    // also identifies the class its instances belong to
+    pub fn root() -> Rc<Shape> {
+        Rc::new(Shape::with_slots(HashMap::new()))
+    }
+
+    fn with_slots(slots: HashMap<Identifier, usize>) -> Shape {
+        Shape {
+            id: NEXT_SHAPE_ID.fetch_add(1, Ordering::Relaxed),
+            slots,
+            transitions: RefCell::new(HashMap::new()),
+        }
+    }
+
+    pub fn id(&self) -> ShapeId {
+        self.id
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    pub fn slot(&self, name: &Identifier) -> Option<usize> {
+        self.slots.get(name).copied()
+    }
+
+    pub fn with_field(self: &Rc<Shape>, name: &Identifier) -> Rc<Shape> {
+        if let Some(shape) = self.transitions.borrow().get(name) {
+            return shape.clone();
+        }
+
+        let mut slots = self.slots.clone();
+        slots.insert(name.clone(), self.slots.len());
+        let shape = Rc::new(Shape::with_slots(slots));
+
+        self.transitions
+            .borrow_mut()
+            .insert(name.clone(), shape.clone());
+        shape
+    }
+}
+
+pub struct Instance {
+    class: Rc<Class>,
+    shape: Rc<Shape>,
+    fields: Vec<Value>,
+}
+
+impl Instance {
+    pub fn new(class: Rc<Class>) -> Instance {
+        Instance {
+            shape: class.shape().clone(),
+            class,
+            fields: Vec::new(),
+        }
+    }
+
+    pub fn class(&self) -> &Rc<Class> {
+        &self.class
+    }
+
+    pub fn shape(&self) -> &Rc<Shape> {
+        &self.shape
+    }
+
+    pub fn get(&self, name: &Identifier) -> Option<&Value> {
+        self.shape.slot(name).map(|slot| &self.fields[slot])
+    }
+
+    pub fn field(&self, slot: usize) -> &Value {
+        &self.fields[slot]
+    }
+
+    pub fn set(&mut self, name: &Identifier, value: Value) {
+        match self.shape.slot(name) {
+            Some(slot) => self.fields[slot] = value,
+            None => {
+                self.shape = self.shape.with_field(name);
+                self.fields.push(value);
+            }
+        }
+    }
+}
+
+// fields can refer back to the instance, so only the class is printed
+impl fmt::Debug for Instance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} instance", self.class.name)
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl CacheStats {
+    pub fn hit_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            total => self.hits as f64 / total as f64,
+        }
+    }
+}
+
+impl Add for CacheStats {
+    type Output = CacheStats;
+
+    fn add(self, other: CacheStats) -> CacheStats {
+        CacheStats {
+            hits: self.hits + other.hits,
+            misses: self.misses + other.misses,
+        }
+    }
+}
+
+// Shapes a single access site remembers before giving up on caching
+pub const POLYMORPHIC_LIMIT: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CacheState {
+    Uninitialized,
+    Monomorphic,
+    Polymorphic,
+    Megamorphic,
+}
+
+// What a property name resolves to on instances of one shape. The class of a
+// shape never changes, so a method found for it stays valid too.
+#[derive(Clone)]
+pub enum Property {
+    Field(usize),
+    Method(Rc<Closure>),
+}
+
+// Per access site cache from the shape of the receiver to the field slot or
+// method the property resolves to, so repeated accesses on same shaped
+// objects skip the lookup. It lives in the AST node of its site, so it's
+// updated through a shared reference. It's runtime state rather than syntax:
+// every cache compares equal, a cloned node starts out with an empty one and
+// serde skips it. The JIT compiles the shapes a site has seen into guards in
+// front of the field loads.
+#[derive(Default)]
+pub struct InlineCache {
+    entries: RefCell<Vec<(ShapeId, Property)>>,
+    megamorphic: Cell<bool>,
+}
+
+impl InlineCache {
+    pub fn state(&self) -> CacheState {
+        match (self.megamorphic.get(), self.entries.borrow().len()) {
+            (true, _) => CacheState::Megamorphic,
+            (false, 0) => CacheState::Uninitialized,
+            (false, 1) => CacheState::Monomorphic,
+            _ => CacheState::Polymorphic,
+        }
+    }
+
+    fn lookup(&self, shape: ShapeId) -> Option<Property> {
+        self.entries
+            .borrow()
+            .iter()
+            .find(|(id, _)| *id == shape)
+            .map(|(_, property)| property.clone())
+    }
+
+    // The shapes seen so far and the slot of the field in each. None unless
+    // the site only ever read fields and isn't megamorphic, as that's all
+    // the JIT compiles.
+    pub fn field_slots(&self) -> Option<Vec<(ShapeId, usize)>> {
+        if self.megamorphic.get() {
+            return None;
+        }
+        self.entries
+            .borrow()
+            .iter()
+            .map(|(shape, property)| match property {
+                Property::Field(slot) => Some((*shape, *slot)),
+                Property::Method(_) => None,
+            })
+            .collect()
+    }
+
+    // fields shadow methods, None when the instance has neither
+    pub fn get(
+        &self,
+        instance: &Instance,
+        name: &Identifier,
+        stats: &mut CacheStats,
+    ) -> Option<Property> {
+        let shape = instance.shape();
+        if let Some(property) = self.lookup(shape.id()) {
+            stats.hits += 1;
+            return Some(property);
+        }
+
+        stats.misses += 1;
+        let property = match shape.slot(name) {
+            Some(slot) => Property::Field(slot),
+            None => Property::Method(instance.class().find_method(name)?.clone()),
+        };
+        self.remember(shape.id(), property.clone());
+        Some(property)
+    }
+
+    pub fn set(
+        &self,
+        instance: &mut Instance,
+        name: &Identifier,
+        value: Value,
+        stats: &mut CacheStats,
+    ) {
+        let shape = instance.shape().clone();
+        if let Some(Property::Field(slot)) = self.lookup(shape.id()) {
+            stats.hits += 1;
+            instance.fields[slot] = value;
+            return;
+        }
+
+        // adding a new field changes the shape, only stores to existing
+        // fields are worth caching
+        stats.misses += 1;
+        if let Some(slot) = shape.slot(name) {
+            self.remember(shape.id(), Property::Field(slot));
+        }
+        instance.set(name, value);
+    }
+
+    fn remember(&self, shape: ShapeId, property: Property) {
+        if self.megamorphic.get() {
+            return;
+        }
+        let mut entries = self.entries.borrow_mut();
+        if entries.len() == POLYMORPHIC_LIMIT {
+            entries.clear();
+            self.megamorphic.set(true);
+            return;
+        }
+        entries.push((shape, property));
+    }
+}
+
+impl Clone for InlineCache {
+    fn clone(&self) -> InlineCache {
+        InlineCache::default()
+    }
+}
+
+impl PartialEq for InlineCache {
+    fn eq(&self, _: &InlineCache) -> bool {
+        true
+    }
+}
+
+impl fmt::Debug for InlineCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "InlineCache({:?})", self.state())
+    }
+}
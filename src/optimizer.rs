@@ -0,0 +1,134 @@
+use std::rc::Rc;
+
+use crate::ast::{Expression, Function, Statement};
+use crate::interpreter::{binary, unary, Value};
+use crate::lexer::TokenKind;
+
+// Rewrites the AST before codegen, folding operations on literals and
+// dropping `if` branches that can never run. Folding goes through the same
+// operator functions the interpreter uses, and anything that would raise a
+// runtime error is left alone so the error still happens at runtime.
+pub fn optimize(statements: Vec<Statement>) -> Vec<Statement> {
+    statements
+        .into_iter()
+        .filter_map(optimize_statement)
+        .collect()
+}
+
+fn optimize_statement(statement: Statement) -> Option<Statement> {
+    let statement = match statement {
+        Statement::Expression(expression) => Statement::Expression(fold(expression)),
+        Statement::Print(expression) => Statement::Print(fold(expression)),
+        Statement::Var(name, initializer) => Statement::Var(name, initializer.map(fold)),
+        Statement::Block(statements) => Statement::Block(optimize(statements)),
+        Statement::If(condition, then_branch, else_branch) => {
+            let condition = fold(condition);
+            let then_branch = optimize_statement(*then_branch);
+            let else_branch = else_branch.and_then(|branch| optimize_statement(*branch));
+
+            match literal(&condition) {
+                Some(value) if value.is_truthy() => return then_branch,
+                Some(_) => return else_branch,
+                None => {}
+            }
+
+            // an emptied out then branch still has to exist for the if to be valid
+            let then_branch = then_branch.unwrap_or(Statement::Block(Vec::new()));
+            Statement::If(condition, Box::new(then_branch), else_branch.map(Box::new))
+        }
+        Statement::While(condition, body) => {
+            let body = optimize_statement(*body).unwrap_or(Statement::Block(Vec::new()));
+            Statement::While(fold(condition), Box::new(body))
+        }
+        Statement::Function(function) => Statement::Function(optimize_function(function)),
+        Statement::Return(line, value) => Statement::Return(line, value.map(fold)),
+        Statement::Class(name, methods) => {
+            Statement::Class(name, methods.into_iter().map(optimize_function).collect())
+        }
+    };
+    Some(statement)
+}
+
+// nothing has run the declaration yet, so taking it out of its Rc never
+// has to copy it
+fn optimize_function(function: Rc<Function>) -> Rc<Function> {
+    let mut function = Rc::unwrap_or_clone(function);
+    function.body = optimize(function.body);
+    Rc::new(function)
+}
+
+pub fn fold(expression: Expression) -> Expression {
+    match expression {
+        Expression::Grouping(expression) => {
+            let expression = fold(*expression);
+            match literal(&expression) {
+                Some(_) => expression,
+                None => Expression::Grouping(Box::new(expression)),
+            }
+        }
+        Expression::Unary(operator, expression) => {
+            let expression = fold(*expression);
+            let folded = literal(&expression)
+                .and_then(|value| unary(&operator, value).ok())
+                .and_then(to_expression);
+            folded.unwrap_or_else(|| Expression::Unary(operator, Box::new(expression)))
+        }
+        Expression::Binary(left, operator, right) => {
+            let left = fold(*left);
+            let right = fold(*right);
+            // a literal on the left of `and`/`or` decides which operand is
+            // the result, whatever the right one is
+            if let (Some(l), TokenKind::And | TokenKind::Or) = (literal(&left), &operator) {
+                return match (&operator, l.is_truthy()) {
+                    (TokenKind::And, false) | (TokenKind::Or, true) => left,
+                    _ => right,
+                };
+            }
+            let folded = match (literal(&left), literal(&right)) {
+                (Some(l), Some(r)) => binary(l, &operator, r).ok().and_then(to_expression),
+                _ => None,
+            };
+            folded.unwrap_or_else(|| Expression::Binary(Box::new(left), operator, Box::new(right)))
+        }
+        Expression::Stringify(expression) => {
+            let expression = fold(*expression);
+            match literal(&expression) {
+                Some(value) => Expression::String(value.to_string()),
+                None => Expression::Stringify(Box::new(expression)),
+            }
+        }
+        Expression::Call(callee, arguments) => Expression::Call(
+            Box::new(fold(*callee)),
+            arguments.into_iter().map(fold).collect(),
+        ),
+        Expression::Get(object, name, cache) => {
+            Expression::Get(Box::new(fold(*object)), name, cache)
+        }
+        Expression::Set(object, name, value, cache) => {
+            Expression::Set(Box::new(fold(*object)), name, Box::new(fold(*value)), cache)
+        }
+        Expression::Assign(variable, value) => Expression::Assign(variable, Box::new(fold(*value))),
+        expression => expression,
+    }
+}
+
+fn literal(expression: &Expression) -> Option<Value> {
+    match expression {
+        Expression::Number(num) => Some(Value::Number(*num)),
+        Expression::String(s) => Some(Value::String(s.clone())),
+        Expression::Bool(b) => Some(Value::Bool(*b)),
+        Expression::Nil => Some(Value::Nil),
+        _ => None,
+    }
+}
+
+fn to_expression(value: Value) -> Option<Expression> {
+    match value {
+        Value::Number(num) => Some(Expression::Number(num)),
+        Value::String(s) => Some(Expression::String(s)),
+        Value::Bool(b) => Some(Expression::Bool(b)),
+        Value::Nil => Some(Expression::Nil),
+        // functions, classes and instances have no literal syntax
+        _ => None,
+    }
+}
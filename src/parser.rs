@@ -1,24 +1,45 @@
-use crate::ast::Expression;
-use crate::lexer::{Lexer, Token, TokenKind};
+use crate::ast::{Expression, Function, Statement, Variable};
+use crate::lexer::{Lexer, LexerOptions, Token, TokenKind};
+use crate::limits::stack_exhausted;
+use crate::object::InlineCache;
+use crate::source::Span;
+use crate::symbol::Symbol;
+use crate::tier::TieredFunction;
+use std::collections::HashMap;
 use std::fmt;
+use std::rc::Rc;
 
+#[derive(Clone)]
 pub enum ParseError {
     UnexpectedError(&'static str),
+    Expected(&'static str, u64),
+    // an error token from the lexer, with its message
+    Lexical(String, u64),
 }
 
 impl fmt::Debug for ParseError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match *self {
+        match self {
             ParseError::UnexpectedError(msg) => write!(f, "unexpected {}", msg),
+            ParseError::Expected(msg, line) => write!(f, "[line {}] Error: {}", line, msg),
+            ParseError::Lexical(msg, line) => write!(f, "[line {}] Error: {}", line, msg),
         }
     }
 }
 
 pub type ParseResult = Result<Expression, ParseError>;
+pub type StatementResult = Result<Statement, ParseError>;
+
+// Lox caps argument and parameter lists so a call fits a one byte operand
+const MAX_ARGUMENTS: usize = 255;
+
+// Deeper source is an error rather than a stack overflow in the recursive
+// descent, as is running low on the thread's stack before getting this deep
+const MAX_NESTING: usize = 256;
 
 // Precedence goes from lowest to highest descending None being lowest
 #[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
-enum Precedence {
+pub enum Precedence {
     None = 0,
     Assignment, // =
     Or,
@@ -32,112 +53,234 @@ enum Precedence {
     Primary,
 }
 
-impl From<Token> for Precedence {
-    fn from(token: Token) -> Self {
-        match token.kind {
-            TokenKind::Equal => Precedence::Assignment,
-            TokenKind::IsEqual => Precedence::Equality,
-            TokenKind::LessEqual
-            | TokenKind::Less
-            | TokenKind::GreaterEqual
-            | TokenKind::Greater => Precedence::Comparison,
-            TokenKind::Plus | TokenKind::Minus => Precedence::Term,
-            TokenKind::Star | TokenKind::Slash => Precedence::Factor,
-            TokenKind::Bang => Precedence::Unary,
-            TokenKind::Dot => Precedence::Call,
-            _ => Precedence::None,
+impl Precedence {
+    // the level binding one step tighter, used for the right operand of a
+    // left associative operator so an operator of the same level stops it
+    pub(crate) fn next(self) -> Precedence {
+        match self {
+            Precedence::None => Precedence::Assignment,
+            Precedence::Assignment => Precedence::Or,
+            Precedence::Or => Precedence::And,
+            Precedence::And => Precedence::Equality,
+            Precedence::Equality => Precedence::Comparison,
+            Precedence::Comparison => Precedence::Term,
+            Precedence::Term => Precedence::Factor,
+            Precedence::Factor => Precedence::Unary,
+            Precedence::Unary => Precedence::Call,
+            Precedence::Call | Precedence::Primary => Precedence::Primary,
         }
     }
 }
 
-impl From<TokenKind> for Precedence {
-    fn from(kind: TokenKind) -> Self {
-        match kind {
-            TokenKind::Equal => Precedence::Assignment,
-            TokenKind::IsEqual => Precedence::Equality,
-            TokenKind::LessEqual
-            | TokenKind::Less
-            | TokenKind::GreaterEqual
-            | TokenKind::Greater => Precedence::Comparison,
-            TokenKind::Plus | TokenKind::Minus => Precedence::Term,
-            TokenKind::Star | TokenKind::Slash => Precedence::Factor,
-            TokenKind::Bang => Precedence::Unary,
-            TokenKind::Dot => Precedence::Call,
-            _ => Precedence::None,
-        }
-    }
+// Parses an expression starting at the token just consumed
+type PrefixFn<'src> = fn(&mut Parser<'src>) -> ParseResult;
+// Parses the rest of an expression whose left operand is already parsed
+type InfixFn<'src> = fn(&mut Parser<'src>, Expression) -> ParseResult;
+
+// How a token behaves at the start of an expression and between two
+// operands, and how tightly it binds in the latter position
+#[derive(Clone, Copy)]
+pub struct ParseRule<'src> {
+    prefix: Option<PrefixFn<'src>>,
+    infix: Option<InfixFn<'src>>,
+    precedence: Precedence,
 }
 
-impl From<&Token> for Precedence {
-    fn from(token: &Token) -> Self {
-        match token.kind {
-            TokenKind::Equal => Precedence::Assignment,
-            TokenKind::IsEqual => Precedence::Equality,
-            TokenKind::LessEqual
-            | TokenKind::Less
-            | TokenKind::GreaterEqual
-            | TokenKind::Greater => Precedence::Comparison,
-            TokenKind::Plus | TokenKind::Minus => Precedence::Term,
-            TokenKind::Star | TokenKind::Slash => Precedence::Factor,
-            TokenKind::Bang => Precedence::Unary,
-            TokenKind::Dot => Precedence::Call,
-            _ => Precedence::None,
+impl<'src> ParseRule<'src> {
+    fn new(
+        prefix: Option<PrefixFn<'src>>,
+        infix: Option<InfixFn<'src>>,
+        precedence: Precedence,
+    ) -> ParseRule<'src> {
+        ParseRule {
+            prefix,
+            infix,
+            precedence,
         }
     }
-}
 
-impl From<&TokenKind> for Precedence {
-    fn from(kind: &TokenKind) -> Self {
-        match kind {
-            TokenKind::Equal => Precedence::Assignment,
-            TokenKind::IsEqual => Precedence::Equality,
-            TokenKind::LessEqual
-            | TokenKind::Less
-            | TokenKind::GreaterEqual
-            | TokenKind::Greater => Precedence::Comparison,
-            TokenKind::Plus | TokenKind::Minus => Precedence::Term,
-            TokenKind::Star | TokenKind::Slash => Precedence::Factor,
-            TokenKind::Bang => Precedence::Unary,
-            TokenKind::Dot => Precedence::Call,
-            _ => Precedence::None,
-        }
+    // a token that can't appear in an expression
+    fn none() -> ParseRule<'src> {
+        ParseRule::new(None, None, Precedence::None)
+    }
+
+    // a left associative binary operator like `%`
+    pub fn infix(precedence: Precedence) -> ParseRule<'src> {
+        ParseRule::new(None, Some(Parser::parse_binary), precedence)
+    }
+
+    // a right associative binary operator like `^`
+    pub fn infix_right(precedence: Precedence) -> ParseRule<'src> {
+        ParseRule::new(None, Some(Parser::parse_binary_right), precedence)
+    }
+
+    // a prefix operator, binding as tightly as `-` and `!`
+    pub fn prefix() -> ParseRule<'src> {
+        ParseRule::new(Some(Parser::parse_unary), None, Precedence::None)
+    }
+
+    // an operator usable both before an operand and between two, like `-`
+    pub fn prefix_and_infix(precedence: Precedence) -> ParseRule<'src> {
+        ParseRule::new(
+            Some(Parser::parse_unary),
+            Some(Parser::parse_binary),
+            precedence,
+        )
     }
 }
 
-pub struct Parser {
-    previous: Token,
-    current: Token,
-    lexer: Lexer,
+pub struct Parser<'src> {
+    previous: Token<'src>,
+    current: Token<'src>,
+    lexer: Lexer<'src>,
+    // rules for operators registered on top of the Lox grammar
+    operators: HashMap<Symbol, ParseRule<'src>>,
+    // braces consumed so far that haven't been closed yet
+    depth: usize,
+    // expressions, statements and functions currently being parsed inside each other
+    nesting: usize,
+    // errors parse_program_recovering has skipped past, None when parsing
+    // stops at the first one
+    errors: Option<Vec<(ParseError, Span)>>,
+    // error tokens the parser stepped over, in source order
+    lexical_errors: Vec<(ParseError, Span)>,
 }
 
-impl Parser {
-    pub fn new(src: &str) -> Parser {
+impl<'src> Parser<'src> {
+    pub fn new(src: &'src str) -> Parser<'src> {
+        Parser::with_options(src, LexerOptions::default())
+    }
+
+    pub fn with_options(src: &'src str, options: LexerOptions) -> Parser<'src> {
         Parser {
             previous: Token::default_token(),
             current: Token::default_token(),
-            lexer: Lexer::new(src),
+            lexer: Lexer::with_options(src, options),
+            operators: HashMap::new(),
+            depth: 0,
+            nesting: 0,
+            errors: None,
+            lexical_errors: Vec::new(),
         }
     }
 
+    // Extends the grammar with an operator, for example
+    // `parser.register_operator("^", ParseRule::infix_right(Precedence::Factor))`.
+    // The operator ends up in the AST as TokenKind::Operator, so whatever runs
+    // the program has to define what it does. Spellings the lexer refuses
+    // are returned as errors, see Lexer::add_operator.
+    pub fn register_operator(
+        &mut self,
+        spelling: &str,
+        rule: ParseRule<'src>,
+    ) -> Result<(), &'static str> {
+        self.lexer.add_operator(spelling)?;
+        self.operators.insert(Symbol::intern(spelling), rule);
+        Ok(())
+    }
+
+    // the clox rule table, keyed by token kind
+    fn rule(&self, kind: &TokenKind) -> ParseRule<'src> {
+        let (prefix, infix, precedence): (Option<PrefixFn>, Option<InfixFn>, _) = match kind {
+            TokenKind::LPar => (
+                Some(Parser::parse_grouping),
+                Some(Parser::parse_call),
+                Precedence::Call,
+            ),
+            TokenKind::Dot => (None, Some(Parser::parse_dot), Precedence::Call),
+            TokenKind::Minus => (
+                Some(Parser::parse_unary),
+                Some(Parser::parse_binary),
+                Precedence::Term,
+            ),
+            TokenKind::Plus => (None, Some(Parser::parse_binary), Precedence::Term),
+            TokenKind::Slash | TokenKind::Star => {
+                (None, Some(Parser::parse_binary), Precedence::Factor)
+            }
+            TokenKind::Bang => (Some(Parser::parse_unary), None, Precedence::None),
+            TokenKind::IsEqual | TokenKind::NotBang => {
+                (None, Some(Parser::parse_binary), Precedence::Equality)
+            }
+            TokenKind::Greater
+            | TokenKind::GreaterEqual
+            | TokenKind::Less
+            | TokenKind::LessEqual => (None, Some(Parser::parse_binary), Precedence::Comparison),
+            TokenKind::Equal => (None, Some(Parser::parse_assignment), Precedence::Assignment),
+            TokenKind::And => (None, Some(Parser::parse_binary), Precedence::And),
+            TokenKind::Or => (None, Some(Parser::parse_binary), Precedence::Or),
+            TokenKind::Ident(_) => (Some(Parser::parse_variable), None, Precedence::None),
+            TokenKind::Number(_)
+            | TokenKind::String
+            | TokenKind::True
+            | TokenKind::False
+            | TokenKind::Nil => (Some(Parser::parse_primary), None, Precedence::None),
+            TokenKind::Interpolation => (Some(Parser::parse_interpolation), None, Precedence::None),
+            TokenKind::This => (Some(Parser::parse_this), None, Precedence::None),
+            TokenKind::Operator(operator) => {
+                return self
+                    .operators
+                    .get(operator)
+                    .copied()
+                    .unwrap_or_else(ParseRule::none)
+            }
+            _ => return ParseRule::none(),
+        };
+        ParseRule::new(prefix, infix, precedence)
+    }
+
     fn advance(&mut self) {
         self.previous = self.current.clone();
+        match self.previous.kind {
+            TokenKind::LBrace => self.depth += 1,
+            TokenKind::RBrace => self.depth = self.depth.saturating_sub(1),
+            _ => {}
+        }
         loop {
             self.current = self.lexer.next_token();
             match self.current.kind {
                 TokenKind::Error => {
-                    eprintln!("Error {}. Line {}", self.current.lexeme, self.current.line)
+                    let error =
+                        ParseError::Lexical(self.current.lexeme.to_string(), self.current.line);
+                    self.lexical_errors.push((error, self.current.span));
                 }
+                TokenKind::DocComment => {}
                 _ => break,
             }
         }
     }
 
-    fn expect_and_consume(&mut self, expected: TokenKind) -> Result<Token, ParseError> {
+    fn check(&self, expected: &TokenKind) -> bool {
+        self.current.kind == *expected
+    }
+
+    fn matches(&mut self, expected: &TokenKind) -> bool {
+        if self.check(expected) {
+            self.advance();
+            return true;
+        }
+        false
+    }
+
+    fn expect_and_consume(
+        &mut self,
+        expected: TokenKind,
+        msg: &'static str,
+    ) -> Result<Token<'src>, ParseError> {
         if self.current.kind == expected {
             self.advance();
-            return Ok(self.current.clone());
+            Ok(self.previous.clone())
         } else {
-            return Err(ParseError::UnexpectedError("token"));
+            Err(ParseError::Expected(msg, self.current.line))
+        }
+    }
+
+    fn expect_identifier(&mut self, msg: &'static str) -> Result<Variable, ParseError> {
+        match self.current.kind.clone() {
+            TokenKind::Ident(name) => {
+                self.advance();
+                Ok(Variable::at(name, &self.previous))
+            }
+            _ => Err(ParseError::Expected(msg, self.current.line)),
         }
     }
 
@@ -147,6 +290,7 @@ impl Parser {
             TokenKind::True => Ok(Expression::Bool(true)),
             TokenKind::False => Ok(Expression::Bool(false)),
             TokenKind::Nil => Ok(Expression::Nil),
+            TokenKind::String => Ok(Expression::String(self.previous.value().into_owned())),
             _ => Err(ParseError::UnexpectedError("not primary token")),
         }
     }
@@ -157,19 +301,19 @@ impl Parser {
 
     fn parse_grouping(&mut self) -> ParseResult {
         let expression = self.parse_expression()?;
-        self.expect_and_consume(TokenKind::Rpar)?;
+        self.expect_and_consume(TokenKind::Rpar, "Expect ')' after expression.")?;
         Ok(Expression::Grouping(Box::new(expression)))
     }
 
     fn parse_unary(&mut self) -> ParseResult {
         let operator = self.previous.clone().kind;
-        let expression = self.parse_expression()?;
+        let expression = self.parse_precedence(Precedence::Unary)?;
         Ok(Expression::Unary(operator, Box::new(expression)))
     }
 
     fn parse_binary(&mut self, left: Expression) -> ParseResult {
         let operator = self.previous.clone().kind;
-        let right = self.parse_precedence(Precedence::from(&operator))?;
+        let right = self.parse_precedence(self.rule(&operator).precedence.next())?;
         Ok(Expression::Binary(
             Box::new(left),
             operator,
@@ -177,53 +321,483 @@ impl Parser {
         ))
     }
 
+    // parsing the right operand at the operator's own level lets it absorb
+    // further uses of the operator, so `a ^ b ^ c` is `a ^ (b ^ c)`
+    fn parse_binary_right(&mut self, left: Expression) -> ParseResult {
+        let operator = self.previous.clone().kind;
+        let right = self.parse_precedence(self.rule(&operator).precedence)?;
+        Ok(Expression::Binary(
+            Box::new(left),
+            operator,
+            Box::new(right),
+        ))
+    }
+
+    fn parse_call(&mut self, callee: Expression) -> ParseResult {
+        let mut arguments = Vec::new();
+        if !self.check(&TokenKind::Rpar) {
+            loop {
+                if arguments.len() == MAX_ARGUMENTS {
+                    return Err(ParseError::Expected(
+                        "Can't have more than 255 arguments.",
+                        self.current.line,
+                    ));
+                }
+                arguments.push(self.parse_expression()?);
+                if !self.matches(&TokenKind::Comma) {
+                    break;
+                }
+            }
+        }
+        self.expect_and_consume(TokenKind::Rpar, "Expect ')' after arguments.")?;
+
+        Ok(Expression::Call(Box::new(callee), arguments))
+    }
+
+    fn parse_dot(&mut self, object: Expression) -> ParseResult {
+        let name = self.expect_identifier("Expect property name after '.'.")?;
+        Ok(Expression::Get(
+            Box::new(object),
+            name.name,
+            InlineCache::default(),
+        ))
+    }
+
+    // `=` is only ever reached as an infix operator when parsing at
+    // assignment precedence, so `a + b = c` parses `a + b` first and is
+    // rejected here instead of assigning to `b`
+    fn parse_assignment(&mut self, target: Expression) -> ParseResult {
+        let line = self.previous.line;
+        let value = self.parse_precedence(Precedence::Assignment)?;
+        match target {
+            Expression::Variable(variable) => Ok(Expression::Assign(variable, Box::new(value))),
+            Expression::Get(object, name, cache) => {
+                Ok(Expression::Set(object, name, Box::new(value), cache))
+            }
+            _ => Err(ParseError::Expected("Invalid assignment target.", line)),
+        }
+    }
+
     fn parse_variable(&mut self) -> ParseResult {
-        self.advance();
         match self.previous.clone().kind {
-            TokenKind::Ident(s) => {
-                self.expect_and_consume(TokenKind::Equal)?;
-                let expression = self.parse_precedence(Precedence::Assignment)?;
-                Ok(Expression::Var(s, Box::new(expression)))
-            }
+            TokenKind::Ident(name) => Ok(Expression::Variable(Variable::at(name, &self.previous))),
             _ => Err(ParseError::UnexpectedError("Wrong token")),
         }
     }
 
+    // "a${b}c" is lexed as Interpolation("a") b String("c") and desugared to
+    // "a" + str(b) + "c", where str is a Stringify node formatting b the way
+    // print would. The leading string is always kept so the result is a
+    // string concatenation even when the literal starts with `${`.
+    fn parse_interpolation(&mut self) -> ParseResult {
+        let mut expression = Expression::String(self.previous.value().into_owned());
+        loop {
+            let part = Expression::Stringify(Box::new(self.parse_expression()?));
+            expression = Expression::Binary(Box::new(expression), TokenKind::Plus, Box::new(part));
+
+            match self.current.kind {
+                TokenKind::Interpolation | TokenKind::String => self.advance(),
+                _ => {
+                    return Err(ParseError::Expected(
+                        "Expect '}' after interpolated expression.",
+                        self.current.line,
+                    ))
+                }
+            }
+
+            let text = self.previous.value();
+            if !text.is_empty() {
+                expression = Expression::Binary(
+                    Box::new(expression),
+                    TokenKind::Plus,
+                    Box::new(Expression::String(text.into_owned())),
+                );
+            }
+            if self.previous.kind == TokenKind::String {
+                return Ok(expression);
+            }
+        }
+    }
+
+    fn parse_this(&mut self) -> ParseResult {
+        Ok(Expression::This(Variable::at(
+            Symbol::intern("this"),
+            &self.previous,
+        )))
+    }
+
+    // Runs parse one level deeper, every cycle in the grammar goes through here
+    fn nested<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<T, ParseError>,
+    ) -> Result<T, ParseError> {
+        if self.nesting == MAX_NESTING || stack_exhausted() {
+            return Err(ParseError::Expected("Too much nesting.", self.current.line));
+        }
+        self.nesting += 1;
+        let result = parse(self);
+        self.nesting -= 1;
+        result
+    }
+
     fn parse_precedence(&mut self, precedence: Precedence) -> ParseResult {
+        self.nested(|parser| parser.parse_precedence_inner(precedence))
+    }
+
+    fn parse_precedence_inner(&mut self, precedence: Precedence) -> ParseResult {
         self.advance();
         if self.previous == Token::default_token() {
             self.advance()
         }
-        let mut expr = self.parse_prefix()?;
-        while precedence <= Precedence::from(self.current.clone()) {
+
+        let prefix = self
+            .rule(&self.previous.kind)
+            .prefix
+            .ok_or(ParseError::UnexpectedError("prefix"))?;
+        let mut expr = prefix(self)?;
+
+        while precedence <= self.rule(&self.current.kind).precedence {
             self.advance();
-            expr = self.parse_infix(expr)?;
+            let infix = self
+                .rule(&self.previous.kind)
+                .infix
+                .ok_or(ParseError::UnexpectedError("infix"))?;
+            expr = infix(self, expr)?;
         }
         Ok(expr)
     }
 
-    fn parse_prefix(&mut self) -> ParseResult {
-        match self.previous.clone().kind {
-            TokenKind::Number(_) | TokenKind::True | TokenKind::False | TokenKind::Nil => {
-                self.parse_primary()
+    fn parse_declaration(&mut self) -> StatementResult {
+        match self.current.kind {
+            TokenKind::Class => {
+                self.advance();
+                self.parse_class_declaration()
+            }
+            TokenKind::Fun => {
+                self.advance();
+                Ok(Statement::Function(Rc::new(self.parse_function()?)))
+            }
+            TokenKind::Var => {
+                self.advance();
+                self.parse_var_declaration()
             }
-            TokenKind::Var => self.parse_variable(),
-            TokenKind::LPar => self.parse_grouping(),
-            TokenKind::Bang | TokenKind::Minus => self.parse_unary(),
-            _ => Err(ParseError::UnexpectedError("prefix")),
+            _ => self.parse_statement(),
         }
     }
 
-    fn parse_infix(&mut self, left: Expression) -> ParseResult {
-        match self.previous.clone().kind {
-            TokenKind::Minus | TokenKind::Plus | TokenKind::Star | TokenKind::Slash => {
-                self.parse_binary(left)
+    fn parse_class_declaration(&mut self) -> StatementResult {
+        let name = self.expect_identifier("Expect class name.")?;
+        self.expect_and_consume(TokenKind::LBrace, "Expect '{' before class body.")?;
+
+        let mut methods = Vec::new();
+        while !self.check(&TokenKind::RBrace) && !self.check(&TokenKind::Eof) {
+            methods.push(Rc::new(self.parse_function()?));
+        }
+        self.expect_and_consume(TokenKind::RBrace, "Expect '}' after class body.")?;
+
+        Ok(Statement::Class(name, methods))
+    }
+
+    fn parse_function(&mut self) -> Result<Function, ParseError> {
+        self.nested(Self::parse_function_inner)
+    }
+
+    fn parse_function_inner(&mut self) -> Result<Function, ParseError> {
+        let name = self.expect_identifier("Expect function name.")?;
+        self.expect_and_consume(TokenKind::LPar, "Expect '(' after function name.")?;
+
+        let mut params = Vec::new();
+        if !self.check(&TokenKind::Rpar) {
+            loop {
+                if params.len() == MAX_ARGUMENTS {
+                    return Err(ParseError::Expected(
+                        "Can't have more than 255 parameters.",
+                        self.current.line,
+                    ));
+                }
+                params.push(self.expect_identifier("Expect parameter name.")?);
+                if !self.matches(&TokenKind::Comma) {
+                    break;
+                }
+            }
+        }
+        self.expect_and_consume(TokenKind::Rpar, "Expect ')' after parameters.")?;
+        self.expect_and_consume(TokenKind::LBrace, "Expect '{' before function body.")?;
+
+        let body = self.parse_block()?;
+        Ok(Function {
+            name,
+            params,
+            body,
+            tier: TieredFunction::default(),
+        })
+    }
+
+    fn parse_var_declaration(&mut self) -> StatementResult {
+        let name = self.expect_identifier("Expect variable name.")?;
+
+        let initializer = if self.matches(&TokenKind::Equal) {
+            Some(self.parse_expression()?)
+        } else {
+            None
+        };
+        self.expect_and_consume(
+            TokenKind::Semicolon,
+            "Expect ';' after variable declaration.",
+        )?;
+
+        Ok(Statement::Var(name, initializer))
+    }
+
+    fn parse_statement(&mut self) -> StatementResult {
+        self.nested(Self::parse_statement_inner)
+    }
+
+    fn parse_statement_inner(&mut self) -> StatementResult {
+        match self.current.kind {
+            TokenKind::Print => {
+                self.advance();
+                let value = self.parse_expression()?;
+                self.expect_and_consume(TokenKind::Semicolon, "Expect ';' after value.")?;
+                Ok(Statement::Print(value))
+            }
+            TokenKind::LBrace => {
+                self.advance();
+                Ok(Statement::Block(self.parse_block()?))
+            }
+            TokenKind::If => {
+                self.advance();
+                self.parse_if_statement()
+            }
+            TokenKind::While => {
+                self.advance();
+                self.parse_while_statement()
+            }
+            TokenKind::For => {
+                self.advance();
+                self.parse_for_statement()
+            }
+            TokenKind::Return => {
+                self.advance();
+                self.parse_return_statement()
+            }
+            _ => {
+                let expression = self.parse_expression()?;
+                self.expect_and_consume(TokenKind::Semicolon, "Expect ';' after expression.")?;
+                Ok(Statement::Expression(expression))
+            }
+        }
+    }
+
+    fn parse_block(&mut self) -> Result<Vec<Statement>, ParseError> {
+        self.nested(Self::parse_block_inner)
+    }
+
+    fn parse_block_inner(&mut self) -> Result<Vec<Statement>, ParseError> {
+        let mut statements = Vec::new();
+        let depth = self.depth;
+        while !self.check(&TokenKind::RBrace) && !self.check(&TokenKind::Eof) {
+            match self.parse_declaration() {
+                Ok(statement) => statements.push(statement),
+                Err(error) if self.errors.is_some() => self.recover(error, depth),
+                Err(error) => return Err(error),
+            }
+        }
+        // a block still being typed keeps what it has so far
+        match self.expect_and_consume(TokenKind::RBrace, "Expect '}' after block.") {
+            Ok(_) => Ok(statements),
+            Err(error) if self.errors.is_some() => {
+                self.recover(error, depth);
+                Ok(statements)
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    fn parse_if_statement(&mut self) -> StatementResult {
+        self.expect_and_consume(TokenKind::LPar, "Expect '(' after 'if'.")?;
+        let condition = self.parse_expression()?;
+        self.expect_and_consume(TokenKind::Rpar, "Expect ')' after if condition.")?;
+
+        let then_branch = self.parse_statement()?;
+        let else_branch = if self.matches(&TokenKind::Else) {
+            Some(Box::new(self.parse_statement()?))
+        } else {
+            None
+        };
+
+        Ok(Statement::If(condition, Box::new(then_branch), else_branch))
+    }
+
+    fn parse_while_statement(&mut self) -> StatementResult {
+        self.expect_and_consume(TokenKind::LPar, "Expect '(' after 'while'.")?;
+        let condition = self.parse_expression()?;
+        self.expect_and_consume(TokenKind::Rpar, "Expect ')' after condition.")?;
+        let body = self.parse_statement()?;
+
+        Ok(Statement::While(condition, Box::new(body)))
+    }
+
+    // for loops have no node of their own, they are desugared into a while
+    // loop wrapped in a block that scopes the initializer
+    fn parse_for_statement(&mut self) -> StatementResult {
+        self.expect_and_consume(TokenKind::LPar, "Expect '(' after 'for'.")?;
+
+        let initializer = match self.current.kind {
+            TokenKind::Semicolon => {
+                self.advance();
+                None
+            }
+            TokenKind::Var => {
+                self.advance();
+                Some(self.parse_var_declaration()?)
+            }
+            _ => {
+                let expression = self.parse_expression()?;
+                self.expect_and_consume(TokenKind::Semicolon, "Expect ';' after expression.")?;
+                Some(Statement::Expression(expression))
             }
-            _ => Err(ParseError::UnexpectedError("infix")),
+        };
+
+        let condition = if self.check(&TokenKind::Semicolon) {
+            Expression::Bool(true)
+        } else {
+            self.parse_expression()?
+        };
+        self.expect_and_consume(TokenKind::Semicolon, "Expect ';' after loop condition.")?;
+
+        let increment = if self.check(&TokenKind::Rpar) {
+            None
+        } else {
+            Some(self.parse_expression()?)
+        };
+        self.expect_and_consume(TokenKind::Rpar, "Expect ')' after for clauses.")?;
+
+        let mut body = self.parse_statement()?;
+        if let Some(increment) = increment {
+            body = Statement::Block(vec![body, Statement::Expression(increment)]);
+        }
+        body = Statement::While(condition, Box::new(body));
+        if let Some(initializer) = initializer {
+            body = Statement::Block(vec![initializer, body]);
         }
+
+        Ok(body)
+    }
+
+    fn parse_return_statement(&mut self) -> StatementResult {
+        let line = self.previous.line;
+        let value = if self.check(&TokenKind::Semicolon) {
+            None
+        } else {
+            Some(self.parse_expression()?)
+        };
+        self.expect_and_consume(TokenKind::Semicolon, "Expect ';' after return value.")?;
+
+        Ok(Statement::Return(line, value))
     }
 
     pub fn parse(&mut self) -> ParseResult {
-        self.parse_expression()
+        let result = self.parse_expression();
+        self.first_error(result)
+    }
+
+    // whether everything up to the end of the source has been parsed, parse
+    // stops after one expression even if more follows
+    pub fn is_at_end(&self) -> bool {
+        self.check(&TokenKind::Eof)
+    }
+
+    pub fn parse_program(&mut self) -> Result<Vec<Statement>, ParseError> {
+        // prime the parser so current holds the first token
+        self.advance();
+
+        let mut statements = Vec::new();
+        while !self.check(&TokenKind::Eof) {
+            match self.parse_declaration() {
+                Ok(statement) => statements.push(statement),
+                Err(error) => return self.first_error(Err(error)),
+            }
+        }
+        self.first_error(Ok(statements))
+    }
+
+    // Source that didn't lex can't run even when it happens to parse, so a
+    // lexer error is returned unless the parse failed before reaching it
+    fn first_error<T>(&self, result: Result<T, ParseError>) -> Result<T, ParseError> {
+        let (lexical, span) = match self.lexical_errors.first() {
+            Some(first) => first,
+            None => return result,
+        };
+        match result {
+            Err(error) if self.error_span(&error).start < span.start => Err(error),
+            _ => Err(lexical.clone()),
+        }
+    }
+
+    // Like parse_program, but after an error it skips to where the next
+    // statement probably starts and carries on, so one mistake doesn't hide
+    // the rest of the program. Each error comes with the span it was found at.
+    pub fn parse_program_recovering(&mut self) -> (Vec<Statement>, Vec<(ParseError, Span)>) {
+        self.errors = Some(Vec::new());
+        self.advance();
+
+        let mut statements = Vec::new();
+        while !self.check(&TokenKind::Eof) {
+            match self.parse_declaration() {
+                Ok(statement) => statements.push(statement),
+                Err(error) => self.recover(error, 0),
+            }
+        }
+        let mut errors = self.errors.take().unwrap_or_default();
+        errors.append(&mut self.lexical_errors);
+        errors.sort_by_key(|(_, span)| span.start);
+        (statements, errors)
+    }
+
+    // Records the error and skips ahead to the next statement at the given
+    // brace depth. Every declaration consumes a token before it can fail, so
+    // this always makes progress.
+    fn recover(&mut self, error: ParseError, depth: usize) {
+        let span = self.error_span(&error);
+        if let Some(errors) = &mut self.errors {
+            errors.push((error, span));
+        }
+
+        while !self.check(&TokenKind::Eof) && self.depth >= depth {
+            if self.depth == depth {
+                if self.previous.kind == TokenKind::Semicolon {
+                    return;
+                }
+                match self.current.kind {
+                    TokenKind::Class
+                    | TokenKind::Fun
+                    | TokenKind::Var
+                    | TokenKind::For
+                    | TokenKind::If
+                    | TokenKind::While
+                    | TokenKind::Print
+                    | TokenKind::Return
+                    | TokenKind::RBrace => return,
+                    _ => {}
+                }
+            }
+            self.advance();
+        }
+    }
+
+    // Where the error returned by the last parse was found. Expected errors
+    // are about the token the parser was looking at, the others about the
+    // token it had just consumed.
+    pub fn error_span(&self, error: &ParseError) -> Span {
+        match error {
+            ParseError::Expected(..) => self.current.span,
+            ParseError::UnexpectedError(_) => self.previous.span,
+            // the only one ever returned is the first
+            ParseError::Lexical(..) => self
+                .lexical_errors
+                .first()
+                .map_or(Span::default(), |(_, span)| *span),
+        }
     }
 }
@@ -0,0 +1,265 @@
+use std::fmt::{self, Write};
+
+use crate::ast::{Expression, Function, Statement};
+use crate::lexer::TokenKind;
+use crate::parser::Precedence;
+
+// Display turns the AST back into Lox source. Parentheses are added wherever
+// the tree shape would otherwise be lost, so printing and parsing again gives
+// back the same tree, but sugar the parser already removed, like for loops
+// and string interpolation, comes out in its desugared form. The one part of
+// an interpolation left is the conversion of each interpolated value, which
+// has no other syntax and is printed as a string holding just that value.
+
+const INDENT: &str = "    ";
+
+pub fn operator(kind: &TokenKind) -> &str {
+    match kind {
+        TokenKind::Plus => "+",
+        TokenKind::Minus => "-",
+        TokenKind::Star => "*",
+        TokenKind::Slash => "/",
+        TokenKind::Bang => "!",
+        TokenKind::NotBang => "!=",
+        TokenKind::IsEqual => "==",
+        TokenKind::Greater => ">",
+        TokenKind::GreaterEqual => ">=",
+        TokenKind::Less => "<",
+        TokenKind::LessEqual => "<=",
+        TokenKind::And => "and",
+        TokenKind::Or => "or",
+        TokenKind::Operator(operator) => operator.as_str(),
+        _ => "?",
+    }
+}
+
+// How tightly the root of an expression binds. Registered operators aren't
+// known here, so they report None and always get parenthesized.
+fn binding(expression: &Expression) -> Precedence {
+    match expression {
+        Expression::Assign(..) | Expression::Set(..) => Precedence::Assignment,
+        Expression::Binary(_, operator, _) => match operator {
+            TokenKind::Or => Precedence::Or,
+            TokenKind::And => Precedence::And,
+            TokenKind::IsEqual | TokenKind::NotBang => Precedence::Equality,
+            TokenKind::Greater
+            | TokenKind::GreaterEqual
+            | TokenKind::Less
+            | TokenKind::LessEqual => Precedence::Comparison,
+            TokenKind::Plus | TokenKind::Minus => Precedence::Term,
+            TokenKind::Star | TokenKind::Slash => Precedence::Factor,
+            _ => Precedence::None,
+        },
+        // a negative literal is printed with a leading minus
+        Expression::Unary(..) => Precedence::Unary,
+        Expression::Number(num) if num.is_sign_negative() => Precedence::Unary,
+        Expression::Call(..) | Expression::Get(..) => Precedence::Call,
+        _ => Precedence::Primary,
+    }
+}
+
+fn operand(f: &mut fmt::Formatter<'_>, expression: &Expression, min: Precedence) -> fmt::Result {
+    match binding(expression) < min {
+        true => write!(f, "({})", expression),
+        false => write!(f, "{}", expression),
+    }
+}
+
+// Lox has no literals for these, so they are spelled as the division that
+// produces them
+fn number(f: &mut fmt::Formatter<'_>, num: f64) -> fmt::Result {
+    match num {
+        _ if num.is_nan() => write!(f, "(0 / 0)"),
+        f64::INFINITY => write!(f, "(1 / 0)"),
+        f64::NEG_INFINITY => write!(f, "(-1 / 0)"),
+        _ => write!(f, "{}", num),
+    }
+}
+
+fn string(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    f.write_char('"')?;
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\t' => f.write_str("\\t")?,
+            '\r' => f.write_str("\\r")?,
+            // would start an interpolation when those are enabled
+            '$' if chars.peek() == Some(&'{') => f.write_str("\\$")?,
+            c if c.is_control() => write!(f, "\\u{{{:x}}}", c as u32)?,
+            c => f.write_char(c)?,
+        }
+    }
+    f.write_char('"')
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expression::Number(num) => number(f, *num),
+            Expression::String(s) => string(f, s),
+            Expression::Bool(b) => write!(f, "{}", b),
+            Expression::Nil => write!(f, "nil"),
+            Expression::Variable(variable) => write!(f, "{}", variable.name),
+            Expression::This(_) => write!(f, "this"),
+            Expression::Grouping(expression) => write!(f, "({})", expression),
+            Expression::Stringify(expression) => write!(f, "\"${{{}}}\"", expression),
+            Expression::Unary(op, expression) => {
+                write!(f, "{}", operator(op))?;
+                operand(f, expression, Precedence::Unary)
+            }
+            Expression::Binary(left, op, right) => {
+                let precedence = binding(self);
+                // operands of registered operators are parenthesized unless
+                // they are atoms, since their precedence isn't known here
+                let (left_min, right_min) = match precedence {
+                    Precedence::None => (Precedence::Call, Precedence::Call),
+                    _ => (precedence, precedence.next()),
+                };
+                operand(f, left, left_min)?;
+                write!(f, " {} ", operator(op))?;
+                operand(f, right, right_min)
+            }
+            Expression::Call(callee, arguments) => {
+                operand(f, callee, Precedence::Call)?;
+                f.write_char('(')?;
+                for (idx, argument) in arguments.iter().enumerate() {
+                    if idx > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{}", argument)?;
+                }
+                f.write_char(')')
+            }
+            Expression::Get(object, name, _) => {
+                operand(f, object, Precedence::Call)?;
+                write!(f, ".{}", name)
+            }
+            Expression::Set(object, name, value, _) => {
+                operand(f, object, Precedence::Call)?;
+                write!(f, ".{} = {}", name, value)
+            }
+            Expression::Assign(variable, value) => write!(f, "{} = {}", variable.name, value),
+        }
+    }
+}
+
+// Writes statements one per line, indenting everything after the first line
+// by the given depth
+struct Printer<'a, 'f> {
+    f: &'a mut fmt::Formatter<'f>,
+    depth: usize,
+}
+
+impl Printer<'_, '_> {
+    fn newline(&mut self) -> fmt::Result {
+        self.f.write_char('\n')?;
+        for _ in 0..self.depth {
+            self.f.write_str(INDENT)?;
+        }
+        Ok(())
+    }
+
+    // `{` and `}` around statements, each on its own line
+    fn block(&mut self, statements: &[Statement]) -> fmt::Result {
+        if statements.is_empty() {
+            return self.f.write_str("{}");
+        }
+
+        self.f.write_char('{')?;
+        self.depth += 1;
+        for statement in statements {
+            self.newline()?;
+            self.statement(statement)?;
+        }
+        self.depth -= 1;
+        self.newline()?;
+        self.f.write_char('}')
+    }
+
+    // the part of a function after `fun`, also used for methods
+    fn function(&mut self, function: &Function) -> fmt::Result {
+        write!(self.f, "{}(", function.name.name)?;
+        for (idx, param) in function.params.iter().enumerate() {
+            if idx > 0 {
+                self.f.write_str(", ")?;
+            }
+            write!(self.f, "{}", param.name)?;
+        }
+        self.f.write_str(") ")?;
+        self.block(&function.body)
+    }
+
+    fn statement(&mut self, statement: &Statement) -> fmt::Result {
+        match statement {
+            Statement::Expression(expression) => write!(self.f, "{};", expression),
+            Statement::Print(expression) => write!(self.f, "print {};", expression),
+            Statement::Var(name, None) => write!(self.f, "var {};", name.name),
+            Statement::Var(name, Some(initializer)) => {
+                write!(self.f, "var {} = {};", name.name, initializer)
+            }
+            Statement::Block(statements) => self.block(statements),
+            Statement::If(condition, then_branch, else_branch) => {
+                write!(self.f, "if ({}) ", condition)?;
+                match (&**then_branch, else_branch) {
+                    // without braces the else would be parsed as belonging
+                    // to the inner if
+                    (Statement::If(_, _, None), Some(_)) => {
+                        self.block(std::slice::from_ref(then_branch))?
+                    }
+                    _ => self.statement(then_branch)?,
+                }
+                if let Some(else_branch) = else_branch {
+                    match **then_branch {
+                        Statement::Block(_) | Statement::If(_, _, None) => {
+                            self.f.write_char(' ')?
+                        }
+                        _ => self.newline()?,
+                    }
+                    self.f.write_str("else ")?;
+                    self.statement(else_branch)?;
+                }
+                Ok(())
+            }
+            Statement::While(condition, body) => {
+                write!(self.f, "while ({}) ", condition)?;
+                self.statement(body)
+            }
+            Statement::Function(function) => {
+                self.f.write_str("fun ")?;
+                self.function(function)
+            }
+            Statement::Return(_, None) => self.f.write_str("return;"),
+            Statement::Return(_, Some(value)) => write!(self.f, "return {};", value),
+            Statement::Class(name, methods) => {
+                write!(self.f, "class {} {{", name.name)?;
+                if methods.is_empty() {
+                    return self.f.write_char('}');
+                }
+                self.depth += 1;
+                for method in methods {
+                    self.newline()?;
+                    self.function(method)?;
+                }
+                self.depth -= 1;
+                self.newline()?;
+                self.f.write_char('}')
+            }
+        }
+    }
+}
+
+impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Printer { f, depth: 0 }.statement(self)
+    }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("fun ")?;
+        Printer { f, depth: 0 }.function(self)
+    }
+}
@@ -0,0 +1,186 @@
+use std::fs;
+use std::time::Instant;
+
+use crate::ast::{Expression, Statement};
+use crate::interpreter::Interpreter;
+use crate::jit::JIT;
+use crate::lexer::{Lexer, TokenKind};
+use crate::optimizer::{fold, optimize};
+use crate::parser::Parser;
+use crate::resolver::Resolver;
+use crate::sexpr::Sexpr;
+
+// Everything the REPL keeps between inputs. Line editing lives in main, this
+// only evaluates complete inputs and the `:` meta-commands.
+
+pub const HELP: &str = ":ast <code>     print the syntax tree of code
+:opt <code>     print the syntax tree of code after constant folding
+:clif <expr>    print the Cranelift IR the JIT generates for an expression
+:load <path>    run a file in this session
+:reset          forget every global and compiled function
+:time <code>    run code and report how long it took
+:globals        list global variables and their values
+:help           show this message";
+
+// Whether more lines are needed before the input can be run: a bracket or
+// brace is still open, or a string or block comment runs to the end
+pub fn is_incomplete(src: &str) -> bool {
+    let mut depth = 0i64;
+    for token in Lexer::new(src) {
+        match token.kind {
+            TokenKind::LPar | TokenKind::LBrace => depth += 1,
+            TokenKind::Rpar | TokenKind::RBrace => depth -= 1,
+            TokenKind::Error
+                if token.lexeme == "Unterminated string."
+                    || token.lexeme == "Unterminated block comment." =>
+            {
+                return true
+            }
+            _ => {}
+        }
+    }
+    depth > 0
+}
+
+pub struct Session {
+    interpreter: Interpreter,
+    jit: JIT,
+    jit_threshold: u64,
+}
+
+impl Session {
+    pub fn new(jit_threshold: u64) -> Session {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_jit_threshold(jit_threshold);
+        Session {
+            interpreter,
+            jit: JIT::default(),
+            jit_threshold,
+        }
+    }
+
+    // Runs one complete input, returning what to show the user. Errors come
+    // back as Err so the caller can send them to stderr.
+    pub fn eval(&mut self, input: &str) -> Result<String, String> {
+        let input = input.trim();
+        let (command, argument) = match input.strip_prefix(':') {
+            Some(meta) => meta.split_once(char::is_whitespace).unwrap_or((meta, "")),
+            None => return self.run(input),
+        };
+        let argument = argument.trim();
+
+        match command {
+            // a lone expression without its `;` is shown as an expression,
+            // not as the statement the prompt would run it as
+            "ast" => match parse(argument) {
+                Ok(program) => {
+                    let lines: Vec<String> = program.iter().map(|s| Sexpr(s).to_string()).collect();
+                    Ok(lines.join("\n"))
+                }
+                Err(e) => match parse_expression(argument) {
+                    Ok(expression) => Ok(Sexpr(&expression).to_string()),
+                    Err(_) => Err(e),
+                },
+            },
+            "opt" => {
+                let mut program = parse_input(argument)?;
+                resolve(&mut program)?;
+                let lines: Vec<String> = optimize(program)
+                    .iter()
+                    .map(|s| Sexpr(s).to_string())
+                    .collect();
+                Ok(lines.join("\n"))
+            }
+            "clif" => {
+                let expression = parse_expression(argument)?;
+                self.jit.clif(fold(expression))
+            }
+            "load" => {
+                let src = fs::read_to_string(argument)
+                    .map_err(|e| format!("could not read {}: {}", argument, e))?;
+                let mut program = parse(&src)?;
+                resolve(&mut program)?;
+                self.execute(&optimize(program)).map(|()| String::new())
+            }
+            "reset" => {
+                *self = Session::new(self.jit_threshold);
+                Ok(String::new())
+            }
+            "time" => {
+                let start = Instant::now();
+                let output = self.run(argument)?;
+                let elapsed = format!("took {:?}", start.elapsed());
+                match output.is_empty() {
+                    true => Ok(elapsed),
+                    false => Ok(format!("{}\n{}", output, elapsed)),
+                }
+            }
+            "globals" => {
+                let mut globals: Vec<String> = self
+                    .interpreter
+                    .globals()
+                    .map(|(name, value)| format!("{} = {}", name, value))
+                    .collect();
+                globals.sort();
+                Ok(globals.join("\n"))
+            }
+            "help" => Ok(HELP.to_string()),
+            _ => Err(format!("unknown command :{}, try :help", command)),
+        }
+    }
+
+    // Input that is a single expression, with or without its `;`, shows
+    // the value it evaluates to, formatted the way print would. Anything
+    // else runs silently.
+    fn run(&mut self, src: &str) -> Result<String, String> {
+        if src.is_empty() {
+            return Ok(String::new());
+        }
+        let mut program = parse_input(src)?;
+        resolve(&mut program)?;
+
+        match program.as_slice() {
+            [Statement::Expression(expression)] => self
+                .interpreter
+                .evaluate(&fold(expression.clone()))
+                .map(|value| value.to_string())
+                .map_err(|e| e.to_string()),
+            _ => self.execute(&optimize(program)).map(|()| String::new()),
+        }
+    }
+
+    fn execute(&mut self, program: &[Statement]) -> Result<(), String> {
+        self.interpreter.execute(program).map_err(|e| e.to_string())
+    }
+}
+
+fn resolve(program: &mut [Statement]) -> Result<(), String> {
+    Resolver::default().resolve(program).map_err(|errors| {
+        let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        errors.join("\n")
+    })
+}
+
+fn parse(src: &str) -> Result<Vec<Statement>, String> {
+    Parser::new(src)
+        .parse_program()
+        .map_err(|e| format!("{:?}", e))
+}
+
+// what was typed at the prompt, where a lone expression may leave off the
+// `;` that would make it a statement
+fn parse_input(src: &str) -> Result<Vec<Statement>, String> {
+    parse(src).or_else(|e| match parse_expression(src) {
+        Ok(expression) => Ok(vec![Statement::Expression(expression)]),
+        Err(_) => Err(e),
+    })
+}
+
+fn parse_expression(src: &str) -> Result<Expression, String> {
+    let mut parser = Parser::new(src);
+    let expression = parser.parse().map_err(|e| format!("{:?}", e))?;
+    match parser.is_at_end() {
+        true => Ok(expression),
+        false => Err("Expect end of expression.".to_string()),
+    }
+}
@@ -0,0 +1,296 @@
+use std::fmt;
+use std::rc::Rc;
+
+use crate::ast::{Expression, Function, Identifier, Local, Statement, Variable};
+use crate::source::Span;
+use crate::symbol::Symbol;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolveError {
+    pub line: u64,
+    // the lexeme the error is reported at
+    pub at: String,
+    // the name the error is about, empty when only the line is known
+    pub span: Span,
+    pub message: &'static str,
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "[line {}] Error at '{}': {}",
+            self.line, self.at, self.message
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FunctionKind {
+    None,
+    Function,
+    Method,
+    Initializer,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ClassKind {
+    None,
+    Class,
+}
+
+// Walks the AST before execution, reporting scoping mistakes and annotating
+// every local variable with the scope depth and slot it lives in. Anything
+// left unannotated is a global.
+pub struct Resolver {
+    // locals in declaration order, paired with whether their initializer has
+    // finished, so a local's index in its scope is also its slot
+    scopes: Vec<Vec<(Identifier, bool)>>,
+    function: FunctionKind,
+    class: ClassKind,
+    errors: Vec<ResolveError>,
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        Resolver {
+            scopes: Vec::new(),
+            function: FunctionKind::None,
+            class: ClassKind::None,
+            errors: Vec::new(),
+        }
+    }
+}
+
+impl Resolver {
+    pub fn resolve(mut self, statements: &mut [Statement]) -> Result<(), Vec<ResolveError>> {
+        self.resolve_statements(statements);
+
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(self.errors)
+        }
+    }
+
+    fn error(&mut self, line: u64, at: &str, message: &'static str) {
+        self.errors.push(ResolveError {
+            line,
+            at: at.to_string(),
+            span: Span::default(),
+            message,
+        });
+    }
+
+    fn error_at(&mut self, variable: &Variable, at: &str, message: &'static str) {
+        self.errors.push(ResolveError {
+            line: variable.line,
+            at: at.to_string(),
+            span: variable.span,
+            message,
+        });
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(Vec::new());
+    }
+
+    fn end_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    fn declare(&mut self, variable: &mut Variable) {
+        let scope = match self.scopes.last_mut() {
+            Some(scope) => scope,
+            None => return,
+        };
+
+        if scope.iter().any(|(name, _)| *name == variable.name) {
+            self.error_at(
+                variable,
+                variable.name.as_str(),
+                "Already a variable with this name in this scope.",
+            );
+            return;
+        }
+
+        variable.local = Some(Local {
+            depth: 0,
+            slot: scope.len(),
+        });
+        scope.push((variable.name.clone(), false));
+    }
+
+    fn define(&mut self, variable: &Variable) {
+        if let Some(scope) = self.scopes.last_mut() {
+            if let Some(local) = scope
+                .iter_mut()
+                .rev()
+                .find(|(name, _)| *name == variable.name)
+            {
+                local.1 = true;
+            }
+        }
+    }
+
+    fn resolve_local(&mut self, variable: &mut Variable) {
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            if let Some(slot) = scope.iter().rposition(|(name, _)| *name == variable.name) {
+                variable.local = Some(Local { depth, slot });
+                return;
+            }
+        }
+    }
+
+    fn resolve_statements(&mut self, statements: &mut [Statement]) {
+        for statement in statements {
+            self.resolve_statement(statement);
+        }
+    }
+
+    fn resolve_statement(&mut self, statement: &mut Statement) {
+        match statement {
+            Statement::Expression(expression) | Statement::Print(expression) => {
+                self.resolve_expression(expression)
+            }
+            Statement::Var(name, initializer) => {
+                self.declare(name);
+                if let Some(initializer) = initializer {
+                    self.resolve_expression(initializer);
+                }
+                self.define(name);
+            }
+            Statement::Block(statements) => {
+                self.begin_scope();
+                self.resolve_statements(statements);
+                self.end_scope();
+            }
+            Statement::If(condition, then_branch, else_branch) => {
+                self.resolve_expression(condition);
+                self.resolve_statement(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.resolve_statement(else_branch);
+                }
+            }
+            Statement::While(condition, body) => {
+                self.resolve_expression(condition);
+                self.resolve_statement(body);
+            }
+            Statement::Function(function) => {
+                // resolving comes before anything can share the declaration,
+                // so this doesn't copy it
+                let function = Rc::make_mut(function);
+                // define eagerly so the function can refer to itself recursively
+                self.declare(&mut function.name);
+                self.define(&function.name);
+                self.resolve_function(function, FunctionKind::Function);
+            }
+            Statement::Return(line, value) => {
+                if self.function == FunctionKind::None {
+                    self.error(*line, "return", "Can't return from top-level code.");
+                }
+                if let Some(value) = value {
+                    if self.function == FunctionKind::Initializer {
+                        self.error(*line, "return", "Can't return a value from an initializer.");
+                    }
+                    self.resolve_expression(value);
+                }
+            }
+            Statement::Class(name, methods) => {
+                let enclosing_class = self.class;
+                self.class = ClassKind::Class;
+
+                self.declare(name);
+                self.define(name);
+
+                for method in methods {
+                    // methods close over a scope holding `this` in slot 0
+                    self.begin_scope();
+                    self.scopes
+                        .last_mut()
+                        .unwrap()
+                        .push((Symbol::intern("this"), true));
+
+                    let kind = match method.name.name.as_str() {
+                        "init" => FunctionKind::Initializer,
+                        _ => FunctionKind::Method,
+                    };
+                    self.resolve_function(Rc::make_mut(method), kind);
+                    self.end_scope();
+                }
+
+                self.class = enclosing_class;
+            }
+        }
+    }
+
+    fn resolve_function(&mut self, function: &mut Function, kind: FunctionKind) {
+        let enclosing_function = self.function;
+        self.function = kind;
+
+        self.begin_scope();
+        for param in &mut function.params {
+            self.declare(param);
+            self.define(param);
+        }
+        self.resolve_statements(&mut function.body);
+        self.end_scope();
+
+        self.function = enclosing_function;
+    }
+
+    fn resolve_expression(&mut self, expression: &mut Expression) {
+        match expression {
+            Expression::Number(_)
+            | Expression::String(_)
+            | Expression::Bool(_)
+            | Expression::Nil => {}
+            Expression::Variable(variable) => {
+                let in_initializer = self.scopes.last().is_some_and(|scope| {
+                    scope
+                        .iter()
+                        .rev()
+                        .find(|(name, _)| *name == variable.name)
+                        .is_some_and(|(_, defined)| !defined)
+                });
+                if in_initializer {
+                    self.error_at(
+                        variable,
+                        variable.name.as_str(),
+                        "Can't read local variable in its own initializer.",
+                    );
+                }
+                self.resolve_local(variable);
+            }
+            Expression::This(variable) => {
+                if self.class == ClassKind::None {
+                    self.error_at(variable, "this", "Can't use 'this' outside of a class.");
+                    return;
+                }
+                self.resolve_local(variable);
+            }
+            Expression::Binary(left, _, right) => {
+                self.resolve_expression(left);
+                self.resolve_expression(right);
+            }
+            Expression::Grouping(expression)
+            | Expression::Unary(_, expression)
+            | Expression::Stringify(expression) => self.resolve_expression(expression),
+            Expression::Call(callee, arguments) => {
+                self.resolve_expression(callee);
+                for argument in arguments {
+                    self.resolve_expression(argument);
+                }
+            }
+            Expression::Get(object, ..) => self.resolve_expression(object),
+            Expression::Set(object, _, value, _) => {
+                self.resolve_expression(value);
+                self.resolve_expression(object);
+            }
+            Expression::Assign(variable, value) => {
+                self.resolve_expression(value);
+                self.resolve_local(variable);
+            }
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use std::fmt;
+
+use crate::ast::{Expression, Function, Statement};
+use crate::printer::operator;
+
+// Prints AST nodes as S-expressions, one list per node with the node kind
+// first, e.g. `(print (+ 1 (call f x)))`. Unlike Display, which prints Lox
+// source, this shows the tree exactly as stored, groupings included.
+pub struct Sexpr<'a, T>(pub &'a T);
+
+fn list<T>(f: &mut fmt::Formatter<'_>, head: &str, items: &[T]) -> fmt::Result
+where
+    for<'a> Sexpr<'a, T>: fmt::Display,
+{
+    write!(f, "({}", head)?;
+    for item in items {
+        write!(f, " {}", Sexpr(item))?;
+    }
+    write!(f, ")")
+}
+
+impl fmt::Display for Sexpr<'_, Expression> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Expression::Number(num) => write!(f, "{}", num),
+            Expression::String(s) => write!(f, "{:?}", s),
+            Expression::Bool(b) => write!(f, "{}", b),
+            Expression::Nil => write!(f, "nil"),
+            Expression::Variable(variable) => write!(f, "{}", variable.name),
+            Expression::This(_) => write!(f, "this"),
+            Expression::Grouping(expression) => write!(f, "(group {})", Sexpr(&**expression)),
+            Expression::Stringify(expression) => write!(f, "(str {})", Sexpr(&**expression)),
+            Expression::Unary(op, expression) => {
+                write!(f, "({} {})", operator(op), Sexpr(&**expression))
+            }
+            Expression::Binary(left, op, right) => write!(
+                f,
+                "({} {} {})",
+                operator(op),
+                Sexpr(&**left),
+                Sexpr(&**right)
+            ),
+            Expression::Call(callee, arguments) => {
+                write!(f, "(call {}", Sexpr(&**callee))?;
+                for argument in arguments {
+                    write!(f, " {}", Sexpr(argument))?;
+                }
+                write!(f, ")")
+            }
+            Expression::Get(object, name, _) => write!(f, "(get {} {})", Sexpr(&**object), name),
+            Expression::Set(object, name, value, _) => {
+                write!(f, "(set {} {} {})", Sexpr(&**object), name, Sexpr(&**value))
+            }
+            Expression::Assign(variable, value) => {
+                write!(f, "(assign {} {})", variable.name, Sexpr(&**value))
+            }
+        }
+    }
+}
+
+impl fmt::Display for Sexpr<'_, Function> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let function = self.0;
+        write!(f, "(fun {} (", function.name.name)?;
+        for (idx, param) in function.params.iter().enumerate() {
+            if idx > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{}", param.name)?;
+        }
+        write!(f, ")")?;
+        for statement in &function.body {
+            write!(f, " {}", Sexpr(statement))?;
+        }
+        write!(f, ")")
+    }
+}
+
+impl fmt::Display for Sexpr<'_, Statement> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Statement::Expression(expression) => write!(f, "(expr {})", Sexpr(expression)),
+            Statement::Print(expression) => write!(f, "(print {})", Sexpr(expression)),
+            Statement::Var(name, None) => write!(f, "(var {})", name.name),
+            Statement::Var(name, Some(initializer)) => {
+                write!(f, "(var {} {})", name.name, Sexpr(initializer))
+            }
+            Statement::Block(statements) => list(f, "block", statements),
+            Statement::If(condition, then_branch, else_branch) => {
+                write!(f, "(if {} {}", Sexpr(condition), Sexpr(&**then_branch))?;
+                if let Some(else_branch) = else_branch {
+                    write!(f, " {}", Sexpr(&**else_branch))?;
+                }
+                write!(f, ")")
+            }
+            Statement::While(condition, body) => {
+                write!(f, "(while {} {})", Sexpr(condition), Sexpr(&**body))
+            }
+            Statement::Function(function) => write!(f, "{}", Sexpr(&**function)),
+            Statement::Return(_, None) => write!(f, "(return)"),
+            Statement::Return(_, Some(value)) => write!(f, "(return {})", Sexpr(value)),
+            Statement::Class(name, methods) => {
+                write!(f, "(class {}", name.name)?;
+                for method in methods {
+                    write!(f, " {}", Sexpr(&**method))?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
@@ -0,0 +1,163 @@
+// Byte range in a source file, end exclusive
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Span {
+        Span { start, end }
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    pub fn to(self, other: Span) -> Span {
+        Span::new(self.start.min(other.start), self.end.max(other.end))
+    }
+}
+
+// Line and column, both counted from 1
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub line: u64,
+    pub column: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnUnit {
+    // Unicode scalar values, what a human counts as characters
+    Char,
+    // UTF-16 code units, what editors speaking LSP count
+    Utf16,
+}
+
+pub struct SourceFile {
+    name: String,
+    src: String,
+    // byte offset every line starts at
+    line_starts: Vec<usize>,
+}
+
+impl SourceFile {
+    pub fn new(name: &str, src: &str) -> SourceFile {
+        let line_starts = std::iter::once(0)
+            .chain(src.match_indices('\n').map(|(idx, _)| idx + 1))
+            .collect();
+
+        SourceFile {
+            name: name.to_string(),
+            src: src.to_string(),
+            line_starts,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn src(&self) -> &str {
+        &self.src
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+
+    // text of a line, without its line break
+    pub fn line_text(&self, line: u64) -> Option<&str> {
+        let idx = (line as usize).checked_sub(1)?;
+        let start = *self.line_starts.get(idx)?;
+        let end = self
+            .line_starts
+            .get(idx + 1)
+            .copied()
+            .unwrap_or(self.src.len());
+        Some(self.src[start..end].trim_end_matches(['\n', '\r']))
+    }
+
+    pub fn slice(&self, span: Span) -> &str {
+        &self.src[span.start..span.end]
+    }
+
+    // offsets past the end of the file are clamped to the end, and ones in
+    // the middle of a character are rounded down to its start
+    pub fn position(&self, offset: usize, unit: ColumnUnit) -> Position {
+        let mut offset = offset.min(self.src.len());
+        while !self.src.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        let idx = match self.line_starts.binary_search(&offset) {
+            Ok(idx) => idx,
+            Err(idx) => idx - 1,
+        };
+
+        let before = &self.src[self.line_starts[idx]..offset];
+        let column = match unit {
+            ColumnUnit::Char => before.chars().count(),
+            ColumnUnit::Utf16 => before.encode_utf16().count(),
+        };
+
+        Position {
+            line: idx as u64 + 1,
+            column: column as u64 + 1,
+        }
+    }
+
+    // inverse of position, None when the position lies outside the file or
+    // in the middle of a character
+    pub fn offset(&self, position: Position, unit: ColumnUnit) -> Option<usize> {
+        let line_start = *self
+            .line_starts
+            .get((position.line as usize).checked_sub(1)?)?;
+        let text = self.line_text(position.line)?;
+        let mut column = (position.column as usize).checked_sub(1)?;
+
+        for (idx, c) in text.char_indices() {
+            if column == 0 {
+                return Some(line_start + idx);
+            }
+            let width = match unit {
+                ColumnUnit::Char => 1,
+                ColumnUnit::Utf16 => c.len_utf16(),
+            };
+            column = column.checked_sub(width)?;
+        }
+
+        match column {
+            0 => Some(line_start + text.len()),
+            _ => None,
+        }
+    }
+}
+
+pub type FileId = usize;
+
+// Owns every file loaded during a session so spans can be traced back to
+// the file and position they came from
+#[derive(Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    pub fn add(&mut self, name: &str, src: &str) -> FileId {
+        self.files.push(SourceFile::new(name, src));
+        self.files.len() - 1
+    }
+
+    pub fn file(&self, id: FileId) -> &SourceFile {
+        &self.files[id]
+    }
+
+    pub fn files(&self) -> &[SourceFile] {
+        &self.files
+    }
+}
@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, LazyLock, Mutex, Weak};
+
+// An identifier. Symbols interned from the same name share one allocation,
+// so comparing and hashing them is comparing and hashing pointers, and a
+// name is freed along with the last symbol that refers to it.
+#[derive(Clone)]
+pub struct Symbol(Arc<str>);
+
+// Every name interned by any thread. One table for the whole process is what
+// makes pointer equality sound, symbols move between threads along with the
+// ASTs holding them. The table only holds weak references so it never keeps
+// a name alive, dead entries are swept as it grows.
+static INTERNER: LazyLock<Mutex<Interner>> = LazyLock::new(Mutex::default);
+
+#[derive(Default)]
+struct Interner {
+    names: HashMap<Box<str>, Weak<str>>,
+    // table size at which dead entries are swept next
+    sweep_at: usize,
+}
+
+impl Interner {
+    fn intern(&mut self, name: &str) -> Symbol {
+        if let Some(symbol) = self.names.get(name).and_then(Weak::upgrade) {
+            return Symbol(symbol);
+        }
+
+        if self.names.len() >= self.sweep_at {
+            self.names.retain(|_, symbol| symbol.strong_count() > 0);
+            self.sweep_at = (self.names.len() * 2).max(64);
+        }
+        let symbol: Arc<str> = Arc::from(name);
+        self.names.insert(name.into(), Arc::downgrade(&symbol));
+        Symbol(symbol)
+    }
+}
+
+impl Symbol {
+    pub fn intern(name: &str) -> Symbol {
+        // the table is never left half updated, so a panic elsewhere while
+        // holding the lock doesn't make it unusable
+        let mut interner = INTERNER.lock().unwrap_or_else(|e| e.into_inner());
+        interner.intern(name)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+// a live name is only ever interned once, so the pointer is the identity
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Symbol) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Symbol {}
+
+impl Hash for Symbol {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (Arc::as_ptr(&self.0) as *const u8 as usize).hash(state)
+    }
+}
+
+impl PartialOrd for Symbol {
+    fn partial_cmp(&self, other: &Symbol) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Symbol {
+    fn cmp(&self, other: &Symbol) -> std::cmp::Ordering {
+        self.0.cmp(&other.0)
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+// symbols are serialized as their name and interned again when read back
+#[cfg(feature = "serde")]
+impl serde::Serialize for Symbol {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Symbol {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = <std::borrow::Cow<'de, str>>::deserialize(deserializer)?;
+        Ok(Symbol::intern(&name))
+    }
+}
@@ -0,0 +1,141 @@
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::rc::Rc;
+
+use crate::ast::Function;
+use crate::interpreter::Value;
+use crate::jit::{CompiledFunction, JIT};
+use crate::object::{CacheStats, Instance};
+
+// Number of times a function runs in the interpreter before it gets compiled
+pub const DEFAULT_JIT_THRESHOLD: u64 = 100;
+
+// Times compiled code may bail out before its function stays interpreted.
+// Sites go megamorphic after a few shapes and aren't compiled then, this
+// catches the rest, like fields that stop holding numbers.
+const DEOPTIMIZATION_LIMIT: u64 = 8;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Counters {
+    pub calls: u64,
+    pub backedges: u64,
+}
+
+impl Counters {
+    // calls and loop iterations both count towards promoting a function, so a
+    // function called once that spins in a long loop still gets compiled
+    pub fn hotness(&self) -> u64 {
+        self.calls + self.backedges
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tier {
+    Interpreted,
+    Compiled,
+}
+
+// Tiering state of one function declaration, kept in its AST node so every
+// closure and bound method made from the declaration shares it. Like an
+// InlineCache it's runtime state rather than syntax: it's updated through a
+// shared reference, every one compares equal, a cloned node starts out cold
+// and serde skips it.
+#[derive(Default)]
+pub struct TieredFunction {
+    counters: Cell<Counters>,
+    compiled: RefCell<Option<CompiledFunction>>,
+    deoptimizations: Cell<u64>,
+    // set once the JIT rejects the body so we don't retry on every call
+    jit_failed: Cell<bool>,
+}
+
+impl TieredFunction {
+    pub fn counters(&self) -> Counters {
+        self.counters.get()
+    }
+
+    pub fn tier(&self) -> Tier {
+        match *self.compiled.borrow() {
+            Some(_) => Tier::Compiled,
+            None => Tier::Interpreted,
+        }
+    }
+
+    pub fn record_backedge(&self) {
+        let mut counters = self.counters.get();
+        counters.backedges += 1;
+        self.counters.set(counters);
+    }
+
+    // Counts a call, compiling the function once it is hotter than threshold
+    pub fn record_call(&self, declaration: &Function, threshold: u64) {
+        let mut counters = self.counters.get();
+        counters.calls += 1;
+        self.counters.set(counters);
+
+        if self.tier() == Tier::Interpreted
+            && !self.jit_failed.get()
+            && counters.hotness() > threshold
+        {
+            match JIT::default().compile_function(declaration) {
+                Ok(code) => *self.compiled.borrow_mut() = Some(code),
+                Err(_) => self.jit_failed.set(true),
+            }
+        }
+    }
+
+    // Runs the compiled code when there is some and the arguments are what
+    // it was specialized for. None means the call has to be interpreted.
+    pub fn call_compiled(
+        &self,
+        arguments: &[Value],
+        this: Option<&Rc<RefCell<Instance>>>,
+        stats: &mut CacheStats,
+    ) -> Option<Value> {
+        let result = {
+            let compiled = self.compiled.borrow();
+            let compiled = compiled.as_ref()?;
+            let numbers = arguments
+                .iter()
+                .map(|argument| match argument {
+                    Value::Number(num) => Some(*num),
+                    _ => None,
+                })
+                .collect::<Option<Vec<f64>>>()?;
+            compiled.call(&numbers, this, stats)
+        };
+        if result.is_none() {
+            self.deoptimize();
+        }
+        result.map(Value::Number)
+    }
+
+    // Throws away code that bailed out, so the next call compiles the
+    // function again against what its inline caches have learned since
+    fn deoptimize(&self) {
+        self.compiled.borrow_mut().take();
+        let deoptimizations = self.deoptimizations.get() + 1;
+        self.deoptimizations.set(deoptimizations);
+        if deoptimizations == DEOPTIMIZATION_LIMIT {
+            self.jit_failed.set(true);
+        }
+    }
+}
+
+impl Clone for TieredFunction {
+    fn clone(&self) -> TieredFunction {
+        TieredFunction::default()
+    }
+}
+
+impl PartialEq for TieredFunction {
+    fn eq(&self, _: &TieredFunction) -> bool {
+        true
+    }
+}
+
+impl fmt::Debug for TieredFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "TieredFunction({:?})", self.tier())
+    }
+}
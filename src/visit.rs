@@ -0,0 +1,217 @@
+use std::rc::Rc;
+
+use crate::ast::{Expression, Function, Statement, Variable};
+
+// Traversal over the AST. Every visit method defaults to the matching walk
+// function, which visits the node's children, so a pass only overrides the
+// nodes it cares about. An override that still wants to reach the children
+// calls the walk function itself, before or after its own work.
+//
+// Names are reported through two hooks: visit_declaration for the place a
+// variable, function, class or parameter is introduced, and visit_variable
+// for every use of one, including assignment targets and `this`.
+
+pub trait Visitor {
+    fn visit_statement(&mut self, statement: &Statement) {
+        walk_statement(self, statement)
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) {
+        walk_expression(self, expression)
+    }
+
+    fn visit_function(&mut self, function: &Function) {
+        walk_function(self, function)
+    }
+
+    fn visit_declaration(&mut self, _variable: &Variable) {}
+
+    fn visit_variable(&mut self, _variable: &Variable) {}
+}
+
+pub fn walk_statements<V: Visitor + ?Sized>(visitor: &mut V, statements: &[Statement]) {
+    for statement in statements {
+        visitor.visit_statement(statement);
+    }
+}
+
+pub fn walk_statement<V: Visitor + ?Sized>(visitor: &mut V, statement: &Statement) {
+    match statement {
+        Statement::Expression(expression) | Statement::Print(expression) => {
+            visitor.visit_expression(expression)
+        }
+        Statement::Var(name, initializer) => {
+            visitor.visit_declaration(name);
+            if let Some(initializer) = initializer {
+                visitor.visit_expression(initializer);
+            }
+        }
+        Statement::Block(statements) => walk_statements(visitor, statements),
+        Statement::If(condition, then_branch, else_branch) => {
+            visitor.visit_expression(condition);
+            visitor.visit_statement(then_branch);
+            if let Some(else_branch) = else_branch {
+                visitor.visit_statement(else_branch);
+            }
+        }
+        Statement::While(condition, body) => {
+            visitor.visit_expression(condition);
+            visitor.visit_statement(body);
+        }
+        Statement::Function(function) => visitor.visit_function(function),
+        Statement::Return(_, value) => {
+            if let Some(value) = value {
+                visitor.visit_expression(value);
+            }
+        }
+        Statement::Class(name, methods) => {
+            visitor.visit_declaration(name);
+            for method in methods {
+                visitor.visit_function(method);
+            }
+        }
+    }
+}
+
+pub fn walk_function<V: Visitor + ?Sized>(visitor: &mut V, function: &Function) {
+    visitor.visit_declaration(&function.name);
+    for param in &function.params {
+        visitor.visit_declaration(param);
+    }
+    walk_statements(visitor, &function.body);
+}
+
+pub fn walk_expression<V: Visitor + ?Sized>(visitor: &mut V, expression: &Expression) {
+    match expression {
+        Expression::Number(_) | Expression::String(_) | Expression::Bool(_) | Expression::Nil => {}
+        Expression::Variable(variable) | Expression::This(variable) => {
+            visitor.visit_variable(variable)
+        }
+        Expression::Grouping(expression)
+        | Expression::Unary(_, expression)
+        | Expression::Stringify(expression) => visitor.visit_expression(expression),
+        Expression::Binary(left, _, right) => {
+            visitor.visit_expression(left);
+            visitor.visit_expression(right);
+        }
+        Expression::Call(callee, arguments) => {
+            visitor.visit_expression(callee);
+            for argument in arguments {
+                visitor.visit_expression(argument);
+            }
+        }
+        Expression::Get(object, ..) => visitor.visit_expression(object),
+        Expression::Set(object, _, value, _) => {
+            visitor.visit_expression(object);
+            visitor.visit_expression(value);
+        }
+        Expression::Assign(variable, value) => {
+            visitor.visit_variable(variable);
+            visitor.visit_expression(value);
+        }
+    }
+}
+
+// Same traversal handing out mutable references, for passes that rewrite
+// the tree in place
+pub trait VisitorMut {
+    fn visit_statement(&mut self, statement: &mut Statement) {
+        walk_statement_mut(self, statement)
+    }
+
+    fn visit_expression(&mut self, expression: &mut Expression) {
+        walk_expression_mut(self, expression)
+    }
+
+    fn visit_function(&mut self, function: &mut Function) {
+        walk_function_mut(self, function)
+    }
+
+    fn visit_declaration(&mut self, _variable: &mut Variable) {}
+
+    fn visit_variable(&mut self, _variable: &mut Variable) {}
+}
+
+pub fn walk_statements_mut<V: VisitorMut + ?Sized>(visitor: &mut V, statements: &mut [Statement]) {
+    for statement in statements {
+        visitor.visit_statement(statement);
+    }
+}
+
+pub fn walk_statement_mut<V: VisitorMut + ?Sized>(visitor: &mut V, statement: &mut Statement) {
+    match statement {
+        Statement::Expression(expression) | Statement::Print(expression) => {
+            visitor.visit_expression(expression)
+        }
+        Statement::Var(name, initializer) => {
+            visitor.visit_declaration(name);
+            if let Some(initializer) = initializer {
+                visitor.visit_expression(initializer);
+            }
+        }
+        Statement::Block(statements) => walk_statements_mut(visitor, statements),
+        Statement::If(condition, then_branch, else_branch) => {
+            visitor.visit_expression(condition);
+            visitor.visit_statement(then_branch);
+            if let Some(else_branch) = else_branch {
+                visitor.visit_statement(else_branch);
+            }
+        }
+        Statement::While(condition, body) => {
+            visitor.visit_expression(condition);
+            visitor.visit_statement(body);
+        }
+        // nothing shares a declaration before it runs, so this doesn't copy
+        Statement::Function(function) => visitor.visit_function(Rc::make_mut(function)),
+        Statement::Return(_, value) => {
+            if let Some(value) = value {
+                visitor.visit_expression(value);
+            }
+        }
+        Statement::Class(name, methods) => {
+            visitor.visit_declaration(name);
+            for method in methods {
+                visitor.visit_function(Rc::make_mut(method));
+            }
+        }
+    }
+}
+
+pub fn walk_function_mut<V: VisitorMut + ?Sized>(visitor: &mut V, function: &mut Function) {
+    visitor.visit_declaration(&mut function.name);
+    for param in &mut function.params {
+        visitor.visit_declaration(param);
+    }
+    walk_statements_mut(visitor, &mut function.body);
+}
+
+pub fn walk_expression_mut<V: VisitorMut + ?Sized>(visitor: &mut V, expression: &mut Expression) {
+    match expression {
+        Expression::Number(_) | Expression::String(_) | Expression::Bool(_) | Expression::Nil => {}
+        Expression::Variable(variable) | Expression::This(variable) => {
+            visitor.visit_variable(variable)
+        }
+        Expression::Grouping(expression)
+        | Expression::Unary(_, expression)
+        | Expression::Stringify(expression) => visitor.visit_expression(expression),
+        Expression::Binary(left, _, right) => {
+            visitor.visit_expression(left);
+            visitor.visit_expression(right);
+        }
+        Expression::Call(callee, arguments) => {
+            visitor.visit_expression(callee);
+            for argument in arguments {
+                visitor.visit_expression(argument);
+            }
+        }
+        Expression::Get(object, ..) => visitor.visit_expression(object),
+        Expression::Set(object, _, value, _) => {
+            visitor.visit_expression(object);
+            visitor.visit_expression(value);
+        }
+        Expression::Assign(variable, value) => {
+            visitor.visit_variable(variable);
+            visitor.visit_expression(value);
+        }
+    }
+}
@@ -0,0 +1,180 @@
+use blox::ast::{Expression, Statement};
+use blox::interpreter::Interpreter;
+use blox::object::{CacheState, CacheStats};
+use blox::parser::Parser;
+use blox::resolver::Resolver;
+
+fn program(src: &str) -> Vec<Statement> {
+    let mut program = Parser::new(src).parse_program().unwrap();
+    Resolver::default().resolve(&mut program).unwrap();
+    program
+}
+
+// state of the cache in the property access of `var v = <access>;`
+fn state(site: &[Statement]) -> CacheState {
+    match site {
+        [Statement::Var(_, Some(Expression::Get(_, _, cache)))] => cache.state(),
+        [Statement::Expression(Expression::Set(_, _, _, cache))] => cache.state(),
+        _ => panic!("not an access site: {:?}", site),
+    }
+}
+
+fn stats(hits: u64, misses: u64) -> CacheStats {
+    CacheStats { hits, misses }
+}
+
+#[test]
+fn repeated_accesses_hit_the_cache() {
+    let mut interpreter = Interpreter::new();
+    interpreter
+        .execute(&program("class P {} var p = P(); p.x = 1;"))
+        .unwrap();
+    let before = interpreter.cache_stats();
+
+    let site = program("var v = p.x;");
+    assert_eq!(state(&site), CacheState::Uninitialized);
+    interpreter.execute(&site).unwrap();
+    assert_eq!(state(&site), CacheState::Monomorphic);
+    interpreter.execute(&site).unwrap();
+    interpreter.execute(&site).unwrap();
+    assert_eq!(
+        interpreter.cache_stats(),
+        before + stats(2, 1),
+        "only the first access misses"
+    );
+
+    let store = program("p.x = 2;");
+    interpreter.execute(&store).unwrap();
+    interpreter.execute(&store).unwrap();
+    assert_eq!(state(&store), CacheState::Monomorphic);
+    assert_eq!(interpreter.cache_stats(), before + stats(3, 2));
+}
+
+#[test]
+fn instances_with_the_same_fields_share_a_shape() {
+    let mut interpreter = Interpreter::new();
+    interpreter
+        .execute(&program(
+            "class P {} var a = P(); a.x = 1; var b = P(); b.x = 2; var o = a;",
+        ))
+        .unwrap();
+    let site = program("var v = o.x;");
+    interpreter.execute(&site).unwrap();
+    interpreter.execute(&program("o = b;")).unwrap();
+    let before = interpreter.cache_stats();
+    interpreter.execute(&site).unwrap();
+    assert_eq!(interpreter.cache_stats(), before + stats(1, 0));
+    assert_eq!(state(&site), CacheState::Monomorphic);
+}
+
+#[test]
+fn adding_a_field_moves_the_instance_to_a_new_shape() {
+    let mut interpreter = Interpreter::new();
+    interpreter
+        .execute(&program("class P {} var p = P(); p.x = 1;"))
+        .unwrap();
+    let site = program("var v = p.x;");
+    interpreter.execute(&site).unwrap();
+
+    interpreter.execute(&program("p.y = 2;")).unwrap();
+    let before = interpreter.cache_stats();
+    interpreter.execute(&site).unwrap();
+    assert_eq!(interpreter.cache_stats(), before + stats(0, 1));
+    assert_eq!(state(&site), CacheState::Polymorphic);
+    // x kept its slot, so the value read through the cache is still right
+    interpreter.execute(&program("p.x = 3;")).unwrap();
+    interpreter.execute(&site).unwrap();
+    let v = interpreter
+        .globals()
+        .find(|(name, _)| name.as_str() == "v")
+        .map(|(_, value)| value.to_string());
+    assert_eq!(v, Some("3".to_string()));
+}
+
+#[test]
+fn sites_seeing_too_many_shapes_go_megamorphic() {
+    let mut interpreter = Interpreter::new();
+    interpreter
+        .execute(&program(
+            "class A {} class B {} class C {} class D {} class E {} var o;",
+        ))
+        .unwrap();
+    let site = program("var v = o.x;");
+    let mut states = Vec::new();
+    for class in ["A", "B", "C", "D", "E"] {
+        interpreter
+            .execute(&program(&format!("o = {}(); o.x = 1;", class)))
+            .unwrap();
+        interpreter.execute(&site).unwrap();
+        states.push(state(&site));
+    }
+    assert_eq!(
+        states,
+        [
+            CacheState::Monomorphic,
+            CacheState::Polymorphic,
+            CacheState::Polymorphic,
+            CacheState::Polymorphic,
+            CacheState::Megamorphic,
+        ]
+    );
+
+    // megamorphic sites still work, they just always miss
+    let before = interpreter.cache_stats();
+    interpreter.execute(&site).unwrap();
+    assert_eq!(interpreter.cache_stats(), before + stats(0, 1));
+}
+
+#[test]
+fn caches_are_not_part_of_the_syntax() {
+    let mut interpreter = Interpreter::new();
+    interpreter
+        .execute(&program("class P {} var p = P(); p.x = 1;"))
+        .unwrap();
+    let site = program("var v = p.x;");
+    interpreter.execute(&site).unwrap();
+
+    let copy = site.clone();
+    assert_eq!(state(&copy), CacheState::Uninitialized);
+    assert_eq!(copy, site);
+    assert_eq!(program("var v = p.x;"), site);
+}
+
+#[test]
+fn caches_survive_new_closures_of_the_same_declaration() {
+    let mut interpreter = Interpreter::new();
+    interpreter
+        .execute(&program(
+            "class P {} var p = P(); p.x = 1;
+             for (var i = 0; i < 10; i = i + 1) {
+                 fun read() { return p.x; }
+                 read();
+             }",
+        ))
+        .unwrap();
+    assert_eq!(interpreter.cache_stats(), stats(9, 2));
+}
+
+#[test]
+fn method_lookups_are_cached_too() {
+    let mut interpreter = Interpreter::new();
+    interpreter
+        .execute(&program("class P { m() { return 1; } } var p = P();"))
+        .unwrap();
+    let site = program("var v = p.m;");
+    for _ in 0..3 {
+        interpreter.execute(&site).unwrap();
+    }
+    assert_eq!(state(&site), CacheState::Monomorphic);
+    assert_eq!(interpreter.cache_stats(), stats(2, 1));
+
+    // a field of the same name shadows the method, on a new shape
+    interpreter.execute(&program("p.m = 2;")).unwrap();
+    interpreter.execute(&site).unwrap();
+    assert_eq!(state(&site), CacheState::Polymorphic);
+    let v = interpreter
+        .globals()
+        .find(|(name, _)| name.as_str() == "v")
+        .map(|(_, value)| value.to_string());
+    assert_eq!(v, Some("2".to_string()));
+}
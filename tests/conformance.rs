@@ -0,0 +1,281 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+// Runs every script under tests/lox through the blox binary and checks it
+// against the annotations in its comments. The scripts are blox's own, not
+// the Crafting Interpreters test suite, but they use that suite's format:
+//
+//   print 1; // expect: 1                     a line of stdout
+//   a(); // expect runtime error: message     stderr, then exit code 70
+//   // [line 3] Error at 'x': message         a compile error, exit code 65
+//   x; // Error at 'x': message               the same, on this line
+//
+// Scripts listed in tests/lox/known_failures are expected to fail. The test
+// only fails when that list is out of date, so it keeps track of how much
+// of each chapter passes without failing on what isn't implemented yet.
+// Run with BLESS=1 to bring the list up to date instead, for example after
+// adding scripts from the upstream suite.
+
+const CORPUS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/lox");
+
+// Chapters of the book and the corpus directories, or top level scripts,
+// they introduce
+const CHAPTERS: &[(&str, &[&str])] = &[
+    (
+        "8. Statements and State",
+        &[
+            "assignment",
+            "block",
+            "bool",
+            "comments",
+            "empty_file.lox",
+            "nil",
+            "number",
+            "precedence.lox",
+            "print",
+            "string",
+            "unexpected_character.lox",
+            "variable",
+        ],
+    ),
+    (
+        "9. Control Flow",
+        &["if", "logical_operator", "while", "for"],
+    ),
+    ("10. Functions", &["call", "function", "return", "closure"]),
+    (
+        "12. Classes",
+        &["class", "constructor", "field", "method", "this"],
+    ),
+    ("13. Inheritance", &["inheritance", "super"]),
+];
+
+#[derive(Default)]
+struct Expectations {
+    output: Vec<String>,
+    errors: Vec<String>,
+    // the message and the line it is reported on
+    runtime_error: Option<(String, usize)>,
+}
+
+impl Expectations {
+    fn parse(src: &str) -> Expectations {
+        let mut expectations = Expectations::default();
+        for (idx, line) in src.lines().enumerate() {
+            let line_number = idx + 1;
+            let comment = match line.find("// ") {
+                Some(start) => &line[start + 3..],
+                None => continue,
+            };
+
+            if let Some(output) = comment.strip_prefix("expect: ") {
+                expectations.output.push(output.to_string());
+            } else if let Some(message) = comment.strip_prefix("expect runtime error: ") {
+                expectations.runtime_error = Some((message.to_string(), line_number));
+            } else if comment.starts_with("Error") {
+                expectations
+                    .errors
+                    .push(format!("[line {}] {}", line_number, comment));
+            } else if let Some((language, error_line, message)) = explicit_error(comment) {
+                // blox is a tree-walker, so errors only the C implementation
+                // reports don't apply
+                if language != Some("c") {
+                    expectations
+                        .errors
+                        .push(format!("[line {}] {}", error_line, message));
+                }
+            }
+        }
+        expectations
+    }
+
+    fn exit_code(&self) -> i32 {
+        match (&self.runtime_error, self.errors.is_empty()) {
+            (_, false) => 65,
+            (Some(_), true) => 70,
+            (None, true) => 0,
+        }
+    }
+}
+
+// `[line 3] Error...` or `[java line 3] Error...`
+fn explicit_error(comment: &str) -> Option<(Option<&str>, usize, &str)> {
+    let (location, message) = comment.strip_prefix('[')?.split_once("] ")?;
+    if !message.starts_with("Error") {
+        return None;
+    }
+    let (language, line) = match location.split_once(' ') {
+        Some((language, rest)) if language != "line" => (Some(language), rest),
+        _ => (None, location),
+    };
+    let line = line.strip_prefix("line ")?.parse().ok()?;
+    Some((language, line, message))
+}
+
+// Every way the script's run differs from its annotations
+fn check(path: &Path) -> Vec<String> {
+    let src = fs::read_to_string(path).unwrap();
+    let expected = Expectations::parse(&src);
+    let run = Command::new(env!("CARGO_BIN_EXE_blox"))
+        .arg(path)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&run.stdout);
+    let stderr = String::from_utf8_lossy(&run.stderr);
+    let stderr: Vec<&str> = stderr.lines().filter(|l| !l.is_empty()).collect();
+
+    let mut problems = Vec::new();
+    let output: Vec<&str> = stdout.lines().collect();
+    if output != expected.output {
+        problems.push(format!(
+            "expected output {:?}, got {:?}",
+            expected.output, output
+        ));
+    }
+
+    if let Some((message, line)) = &expected.runtime_error {
+        let expected_stderr = [message.clone(), format!("[line {}]", line)];
+        if stderr.len() < 2 || stderr[..2] != expected_stderr {
+            problems.push(format!(
+                "expected runtime error {:?}, got {:?}",
+                expected_stderr, stderr
+            ));
+        }
+    } else {
+        let mut missing: Vec<&String> = expected.errors.iter().collect();
+        for line in &stderr {
+            match missing.iter().position(|error| error == line) {
+                Some(idx) => {
+                    missing.remove(idx);
+                }
+                None => problems.push(format!("unexpected error {:?}", line)),
+            }
+        }
+        for error in missing {
+            problems.push(format!("missing error {:?}", error));
+        }
+    }
+
+    let code = run.status.code().unwrap_or(-1);
+    if code != expected.exit_code() {
+        problems.push(format!(
+            "expected exit code {}, got {}",
+            expected.exit_code(),
+            code
+        ));
+    }
+    problems
+}
+
+// Rewrites known_failures to list exactly the failing scripts. Scripts that
+// stay listed keep their place under their comment, new ones go at the end.
+fn bless(failing: &HashSet<String>) {
+    let path = Path::new(CORPUS).join("known_failures");
+    let old = fs::read_to_string(&path).unwrap();
+    let mut listed = HashSet::new();
+    let mut new = String::new();
+    for line in old.lines() {
+        let script = line.trim();
+        if script.is_empty() || script.starts_with('#') || failing.contains(script) {
+            listed.insert(script.to_string());
+            new.push_str(line);
+            new.push('\n');
+        }
+    }
+    let mut unlisted: Vec<&String> = failing.difference(&listed).collect();
+    if !unlisted.is_empty() {
+        unlisted.sort();
+        new.push_str("\n# not sorted into a group yet\n");
+        for script in unlisted {
+            new.push_str(script);
+            new.push('\n');
+        }
+    }
+    fs::write(path, new).unwrap();
+}
+
+// scripts under a corpus entry, relative to the corpus, in a stable order
+fn scripts(entry: &str) -> Vec<String> {
+    let path = Path::new(CORPUS).join(entry);
+    if !path.is_dir() {
+        return vec![entry.to_string()];
+    }
+    let mut scripts: Vec<String> = fs::read_dir(&path)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.ends_with(".lox"))
+        .map(|name| format!("{}/{}", entry, name))
+        .collect();
+    scripts.sort();
+    scripts
+}
+
+#[test]
+fn lox_scripts_match_their_annotations() {
+    let known_failures = fs::read_to_string(Path::new(CORPUS).join("known_failures")).unwrap();
+    let known_failures: HashSet<&str> = known_failures
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect();
+
+    let mut unexpected = Vec::new();
+    let mut failing = HashSet::new();
+    let mut seen = HashSet::new();
+    for (chapter, entries) in CHAPTERS {
+        let mut passed = 0;
+        let mut total = 0;
+        for script in entries.iter().flat_map(|entry| scripts(entry)) {
+            let problems = check(&Path::new(CORPUS).join(&script));
+            total += 1;
+            if problems.is_empty() {
+                passed += 1;
+            } else {
+                failing.insert(script.clone());
+            }
+
+            match (
+                problems.is_empty(),
+                known_failures.contains(script.as_str()),
+            ) {
+                (true, true) => unexpected.push(format!(
+                    "{} passes now, remove it from known_failures",
+                    script
+                )),
+                (false, false) => {
+                    unexpected.push(format!("{} failed:\n  {}", script, problems.join("\n  ")))
+                }
+                _ => {}
+            }
+            seen.insert(script);
+        }
+        println!("{:<28} {:>3}/{:<3}", chapter, passed, total);
+    }
+
+    // everything in the corpus has to belong to a chapter
+    for entry in fs::read_dir(CORPUS).unwrap() {
+        let name = entry.unwrap().file_name().into_string().unwrap();
+        if !name.ends_with(".lox") && !Path::new(CORPUS).join(&name).is_dir() {
+            continue;
+        }
+        for script in scripts(&name) {
+            if !seen.contains(&script) {
+                unexpected.push(format!("{} isn't part of any chapter", script));
+            }
+        }
+    }
+    for script in &known_failures {
+        if !seen.contains(*script) {
+            unexpected.push(format!("known failure {} doesn't exist", script));
+        }
+    }
+
+    if std::env::var_os("BLESS").is_some() {
+        bless(&failing);
+        return;
+    }
+
+    assert!(unexpected.is_empty(), "{}", unexpected.join("\n"));
+}
@@ -0,0 +1,36 @@
+use blox::formatter::format;
+
+#[test]
+fn reformats_spacing_and_indentation() {
+    let src = "fun add(a,b){return a+b;}\nprint add(1,-2)*3;";
+    let expected = "fun add(a, b) {\n    return a + b;\n}\nprint add(1, -2) * 3;\n";
+    assert_eq!(format(src).unwrap(), expected);
+}
+
+#[test]
+fn keeps_comments_and_single_blank_lines() {
+    let src = "// header\nvar a = 1;   // one\n\n\n\n/* two */\nvar b = 2;\n";
+    let expected = "// header\nvar a = 1; // one\n\n/* two */\nvar b = 2;\n";
+    assert_eq!(format(src).unwrap(), expected);
+}
+
+#[test]
+fn keeps_for_loops_and_else_chains() {
+    let src = "for(var i=0;i<2;i=i+1) if(i==0){print i;}else{print -i;}";
+    let expected =
+        "for (var i = 0; i < 2; i = i + 1) if (i == 0) {\n    print i;\n} else {\n    print -i;\n}\n";
+    assert_eq!(format(src).unwrap(), expected);
+}
+
+#[test]
+fn formatting_is_idempotent() {
+    let src = "class A{init(x){this.x=x;}}\nwhile(false){}\nprint A(1).x;";
+    let once = format(src).unwrap();
+    assert_eq!(format(&once).unwrap(), once);
+}
+
+#[test]
+fn rejects_invalid_programs() {
+    assert!(format("print 1 +;").is_err());
+    assert!(format("print \"open;").is_err());
+}
@@ -0,0 +1,73 @@
+use blox::ast::{Expression, Local, Statement, Variable};
+use blox::infer::{infer, infer_locals, infer_with, Type};
+use blox::parser::Parser;
+use blox::resolver::Resolver;
+use blox::symbol::Symbol;
+
+// the body of the first function in src, resolved
+fn body(src: &str) -> Vec<Statement> {
+    let mut program = Parser::new(src).parse_program().unwrap();
+    Resolver::default().resolve(&mut program).unwrap();
+    match program.remove(0) {
+        Statement::Function(function) => function.body.clone(),
+        statement => panic!("expected a function, got {:?}", statement),
+    }
+}
+
+#[test]
+fn infers_literal_expressions() {
+    let ty = |src: &str| infer(&Parser::new(src).parse().unwrap());
+    assert_eq!(ty("1 + 2 * -3"), Type::Number);
+    assert_eq!(ty("\"a\" + \"b\""), Type::String);
+    assert_eq!(ty("1 < 2"), Type::Bool);
+    assert_eq!(ty("!nil"), Type::Bool);
+    assert_eq!(ty("1 + \"a\""), Type::Dynamic);
+    // globals can be reassigned between runs
+    assert_eq!(ty("a + 1"), Type::Dynamic);
+}
+
+#[test]
+fn infers_through_parameters_and_locals() {
+    let body = body("fun f(a, b) { var sum = a + b; var label = \"sum\"; sum = sum * 2; }");
+    let numbers = [Type::Number, Type::Number];
+    assert_eq!(
+        infer_locals(&body, &numbers),
+        Some(vec![Type::Number, Type::Number, Type::Number, Type::String])
+    );
+    // nothing is known about values computed from unknown parameters
+    assert_eq!(
+        infer_locals(&body, &[Type::Dynamic, Type::Dynamic]),
+        Some(vec![
+            Type::Dynamic,
+            Type::Dynamic,
+            Type::Dynamic,
+            Type::String
+        ])
+    );
+
+    let read = |slot| {
+        let mut variable = Variable::new(Symbol::intern("x"), 1);
+        variable.local = Some(Local { depth: 0, slot });
+        Expression::Variable(variable)
+    };
+    let locals = infer_locals(&body, &numbers).unwrap();
+    assert_eq!(infer_with(&read(2), &locals), Type::Number);
+    assert_eq!(infer_with(&read(3), &locals), Type::String);
+    // slots the run doesn't know about aren't assumed to be anything
+    assert_eq!(infer_with(&read(4), &locals), Type::Dynamic);
+}
+
+#[test]
+fn locals_that_change_type_or_control_flow_are_not_inferred() {
+    let numbers = [Type::Number];
+    assert_eq!(
+        infer_locals(&body("fun f(a) { var x = a; x = \"s\"; }"), &numbers),
+        None
+    );
+    assert_eq!(infer_locals(&body("fun f(a) { a = nil; }"), &numbers), None);
+    assert_eq!(
+        infer_locals(&body("fun f(a) { if (a) a = 1; }"), &numbers),
+        None
+    );
+    assert_eq!(infer_locals(&body("fun f(a) { var x; }"), &numbers), None);
+}
@@ -0,0 +1,158 @@
+use blox::interpreter::{Interpreter, RuntimeError};
+use blox::parser::Parser;
+use blox::resolver::Resolver;
+
+fn run(src: &str) -> Result<Interpreter, RuntimeError> {
+    let mut program = Parser::new(src).parse_program().unwrap();
+    Resolver::default().resolve(&mut program).unwrap();
+    let mut interpreter = Interpreter::new();
+    interpreter.execute(&program).map(|()| interpreter)
+}
+
+// the value of a global after running src, as print would show it
+fn global(src: &str, name: &str) -> String {
+    let interpreter = run(src).unwrap_or_else(|e| panic!("{}: {}", src, e));
+    let value = interpreter
+        .globals()
+        .find(|(global, _)| global.as_str() == name)
+        .map(|(_, value)| value.to_string());
+    value.unwrap_or_else(|| panic!("no global {}", name))
+}
+
+fn error(src: &str) -> String {
+    match run(src) {
+        Ok(_) => panic!("{} ran without an error", src),
+        Err(e) => e.to_string(),
+    }
+}
+
+#[test]
+fn functions_take_arguments_and_return_values() {
+    let src = "fun add(a, b) { return a + b; } var r = add(1, 2) * add(3, 4);";
+    assert_eq!(global(src, "r"), "21");
+    // falling off the end returns nil
+    assert_eq!(global("fun f() {} var r = f();", "r"), "nil");
+    assert_eq!(
+        global(
+            "fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); } var r = fib(15);",
+            "r"
+        ),
+        "610"
+    );
+    assert_eq!(global("fun f() {} var r = f;", "r"), "<fn f>");
+}
+
+#[test]
+fn closures_capture_their_environment() {
+    let src = "
+        fun counter() {
+            var count = 0;
+            fun increment() { count = count + 1; return count; }
+            return increment;
+        }
+        var a = counter();
+        var b = counter();
+        a(); a();
+        var r = a() * 10 + b();";
+    assert_eq!(global(src, "r"), "31");
+
+    // a closure sees the variable, not a copy of its value at the time
+    let src = "
+        var r;
+        {
+            var x = \"before\";
+            fun get() { return x; }
+            x = \"after\";
+            r = get();
+        }";
+    assert_eq!(global(src, "r"), "after");
+}
+
+#[test]
+fn classes_make_instances_with_fields_and_methods() {
+    let src = "
+        class Point {
+            init(x, y) { this.x = x; this.y = y; }
+            sum() { return this.x + this.y; }
+        }
+        var p = Point(1, 2);
+        p.x = 10;
+        var r = p.sum();
+        var m = p.sum;
+        var bound = m();";
+    assert_eq!(global(src, "r"), "12");
+    // methods stay bound to the instance they were read from
+    assert_eq!(global(src, "bound"), "12");
+    assert_eq!(global(src, "p"), "Point instance");
+    assert_eq!(global(src, "Point"), "Point");
+
+    // fields shadow methods
+    let src = "class A { m() { return 1; } } var a = A(); a.m = 2; var r = a.m;";
+    assert_eq!(global(src, "r"), "2");
+    // an initializer always returns the instance
+    let src = "class A { init() { return; } } var a = A(); var r = a.init();";
+    assert_eq!(global(src, "r"), "A instance");
+}
+
+#[test]
+fn bad_calls_and_property_access_are_runtime_errors() {
+    assert_eq!(
+        error("fun f(a) {} f(1, 2);"),
+        "Expected 1 arguments but got 2."
+    );
+    assert_eq!(
+        error("class A { init(a) {} } A();"),
+        "Expected 1 arguments but got 0."
+    );
+    assert_eq!(
+        error("\"not a function\"();"),
+        "Can only call functions and classes."
+    );
+    assert_eq!(
+        error("class A {} A().missing;"),
+        "Undefined property 'missing'."
+    );
+    assert_eq!(error("var a = 1; a.b;"), "Only instances have properties.");
+    assert_eq!(error("var a = 1; a.b = 2;"), "Only instances have fields.");
+    assert_eq!(error("undefined = 1;"), "Undefined variable 'undefined'.");
+}
+
+#[test]
+fn values_compare_by_identity_or_value() {
+    let src = "
+        class A {}
+        var a = A();
+        fun f() {}
+        var same = a == a;
+        var different = a == A();
+        var functions = f == f;";
+    assert_eq!(global(src, "same"), "true");
+    assert_eq!(global(src, "different"), "false");
+    assert_eq!(global(src, "functions"), "true");
+}
+
+#[test]
+fn and_or_return_an_operand_and_short_circuit() {
+    assert_eq!(global("var r = 1 and 2;", "r"), "2");
+    assert_eq!(global("var r = nil and 2;", "r"), "nil");
+    assert_eq!(global("var r = false or \"b\";", "r"), "b");
+    assert_eq!(global("var r = 0 or 2;", "r"), "0");
+
+    // the right operand only runs when the left one doesn't decide
+    let src = "
+        var calls = 0;
+        fun f(value) { calls = calls + 1; return value; }
+        var a = f(false) and f(1);
+        var b = f(true) or f(1);
+        var c = f(true) and f(false) or f(2);";
+    assert_eq!(global(src, "calls"), "5");
+    assert_eq!(global(src, "a"), "false");
+    assert_eq!(global(src, "b"), "true");
+    assert_eq!(global(src, "c"), "2");
+
+    assert_eq!(
+        error("var r = true and nil + 1;"),
+        "Operands must be two numbers or two strings."
+    );
+    assert!(run("var r = false and nil + 1;").is_ok());
+}
@@ -0,0 +1,74 @@
+use blox::ast::Expression;
+use blox::interpreter::{Interpreter, Value};
+use blox::jit::JIT;
+use blox::lexer::TokenKind;
+use blox::optimizer::fold;
+use proptest::prelude::*;
+
+// Same comparison as the jit_differential fuzz target, in a form that runs
+// with cargo test: whatever the JIT compiles has to agree with the
+// interpreter, and constant folding mustn't change any result.
+
+fn arb_expression() -> impl Strategy<Value = Expression> {
+    let leaf = prop_oneof![
+        4 => prop_oneof![
+            (-100i32..100).prop_map(|num| num as f64),
+            Just(0.0),
+            Just(-0.0),
+            any::<f64>(),
+        ]
+        .prop_map(Expression::Number),
+        1 => "[a-z]{0,3}".prop_map(Expression::String),
+        1 => any::<bool>().prop_map(Expression::Bool),
+        1 => Just(Expression::Nil),
+    ];
+
+    let binary = prop::sample::select(vec![
+        TokenKind::Plus,
+        TokenKind::Minus,
+        TokenKind::Star,
+        TokenKind::Slash,
+        TokenKind::Less,
+        TokenKind::IsEqual,
+    ]);
+    let unary = prop::sample::select(vec![TokenKind::Minus, TokenKind::Bang]);
+
+    leaf.prop_recursive(8, 128, 2, move |inner| {
+        prop_oneof![
+            4 => (inner.clone(), binary.clone(), inner.clone()).prop_map(|(left, op, right)| {
+                Expression::Binary(Box::new(left), op, Box::new(right))
+            }),
+            2 => (unary.clone(), inner.clone())
+                .prop_map(|(op, expression)| Expression::Unary(op, Box::new(expression))),
+            1 => inner.prop_map(|expression| Expression::Grouping(Box::new(expression))),
+        ]
+    })
+}
+
+// NaNs count as equal, everything else has to match to the bit
+fn same(left: f64, right: f64) -> bool {
+    (left.is_nan() && right.is_nan()) || left.to_bits() == right.to_bits()
+}
+
+proptest! {
+    #[test]
+    fn jit_agrees_with_the_interpreter(expression in arb_expression()) {
+        let expected = Interpreter::new().evaluate(&expression);
+        let folded = Interpreter::new().evaluate(&fold(expression.clone()));
+        match (&folded, &expected) {
+            (Ok(Value::Number(left)), Ok(Value::Number(right))) => {
+                prop_assert!(same(*left, *right), "folding gave {} instead of {}", left, right)
+            }
+            _ => prop_assert_eq!(&folded, &expected),
+        }
+
+        if let Ok(code) = JIT::default().compile_expression(expression.clone()) {
+            match expected {
+                Ok(Value::Number(num)) => {
+                    prop_assert!(same(code(), num), "JIT gave {} instead of {}", code(), num)
+                }
+                other => prop_assert!(false, "JIT compiled an expression evaluating to {:?}", other),
+            }
+        }
+    }
+}
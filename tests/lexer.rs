@@ -0,0 +1,419 @@
+use blox::interpreter::Interpreter;
+use blox::lexer::{Lexer, LexerOptions, Token, TokenKind};
+use blox::parser::Parser;
+use blox::resolver::Resolver;
+use blox::sexpr::Sexpr;
+use blox::source::Span;
+use blox::symbol::Symbol;
+
+fn unicode() -> LexerOptions {
+    LexerOptions {
+        unicode_identifiers: true,
+        ..LexerOptions::default()
+    }
+}
+
+// the source text every token's span covers
+fn spans<'src>(src: &'src str, tokens: &[Token]) -> Vec<&'src str> {
+    tokens
+        .iter()
+        .map(|token| &src[token.span.start..token.span.end])
+        .collect()
+}
+
+#[test]
+fn spans_are_byte_offsets_into_the_source() {
+    let src = "var café = \"naïve 🦀\";\nprint café;";
+    let tokens: Vec<Token> = Lexer::with_options(src, unicode()).collect();
+    assert_eq!(
+        spans(src, &tokens),
+        [
+            "var",
+            "café",
+            "=",
+            "\"naïve 🦀\"",
+            ";",
+            "print",
+            "café",
+            ";"
+        ]
+    );
+    assert_eq!(tokens[4].span, Span::new(25, 26));
+
+    // columns count chars rather than bytes
+    let positions: Vec<(u64, u64)> = tokens.iter().map(|t| (t.line, t.column)).collect();
+    assert_eq!(
+        positions,
+        [
+            (1, 1),
+            (1, 5),
+            (1, 10),
+            (1, 12),
+            (1, 21),
+            (2, 1),
+            (2, 7),
+            (2, 11)
+        ]
+    );
+}
+
+#[test]
+fn lexemes_borrow_from_the_source() {
+    let src = "fun naïve() { return \"日本語\"; }";
+    let range = src.as_bytes().as_ptr_range();
+    for token in Lexer::with_options(src, unicode()) {
+        assert!(
+            range.contains(&token.lexeme.as_ptr()),
+            "{:?} was copied",
+            token
+        );
+    }
+    let strings: Vec<&str> = Lexer::new(src)
+        .filter(|token| token.kind == TokenKind::String)
+        .map(|token| token.lexeme)
+        .collect();
+    assert_eq!(strings, ["日本語"]);
+}
+
+#[test]
+fn errors_cover_whole_multibyte_chars() {
+    let src = "a → b";
+    let tokens: Vec<Token> = Lexer::new(src).collect();
+    assert_eq!(spans(src, &tokens), ["a", "→", "b"]);
+    assert_eq!(tokens[1].kind, TokenKind::Error);
+    assert_eq!(tokens[1].lexeme, "Unexpected character.");
+    assert_eq!(tokens[2].column, 5);
+}
+
+#[test]
+fn long_inputs_lex_in_one_pass() {
+    // quadratic scanning would take minutes on this
+    let src = "var ü = 1;\n".repeat(50_000);
+    let mut lexer = Lexer::with_options(&src, unicode());
+    let count = lexer
+        .by_ref()
+        .inspect(|token| assert_ne!(token.kind, TokenKind::Error))
+        .count();
+    assert_eq!(count, 5 * 50_000);
+    assert!(lexer.is_at_end());
+}
+
+fn kinds(src: &str) -> Vec<TokenKind> {
+    Lexer::new(src).map(|token| token.kind).collect()
+}
+
+#[test]
+fn comments_are_skipped() {
+    let a = TokenKind::Ident(Symbol::intern("a"));
+    assert_eq!(kinds("// nothing but a comment"), []);
+    assert_eq!(
+        kinds("a // trailing\n/ a"),
+        [a.clone(), TokenKind::Slash, a.clone()]
+    );
+    assert_eq!(
+        kinds("a /* inline */ / /**/ a"),
+        [a.clone(), TokenKind::Slash, a.clone()]
+    );
+    // block comments nest
+    assert_eq!(
+        kinds("/* outer /* inner */ still outer */ a"),
+        vec![a.clone()]
+    );
+    assert_eq!(kinds("//// four slashes is a plain comment\na"), [a]);
+
+    // lines inside block comments still count
+    let token = Lexer::new("/* one\ntwo\n*/ three").next_token();
+    assert_eq!((token.line, token.column), (3, 4));
+}
+
+#[test]
+fn doc_comments_are_kept_as_trivia() {
+    let src = "/// Says hi.\n/// Twice.\nfun hi() {}";
+    let tokens: Vec<Token> = Lexer::new(src).collect();
+    assert_eq!(spans(src, &tokens[..2]), ["/// Says hi.", "/// Twice."]);
+    assert!(tokens[..2]
+        .iter()
+        .all(|token| token.kind == TokenKind::DocComment));
+    assert_eq!((tokens[1].line, tokens[1].column), (2, 1));
+    assert_eq!(tokens[2].kind, TokenKind::Fun);
+
+    // the parser skips them like any other comment
+    let program = Parser::new(src).parse_program();
+    assert!(program.is_ok(), "{:?}", program);
+}
+
+#[test]
+fn unterminated_block_comments_are_errors() {
+    for src in ["a /* never closed", "a /* /* nested */ never closed"] {
+        let tokens: Vec<Token> = Lexer::new(src).collect();
+        assert_eq!(tokens.len(), 2, "{}", src);
+        assert_eq!(tokens[1].kind, TokenKind::Error);
+        assert_eq!(tokens[1].lexeme, "Unterminated block comment.");
+        // from the opening `/*` to the end of the input
+        assert_eq!(spans(src, &tokens)[1], &src[2..]);
+        assert_eq!(tokens[1].column, 3);
+    }
+}
+
+fn extended() -> LexerOptions {
+    LexerOptions {
+        extended_numbers: true,
+        ..LexerOptions::default()
+    }
+}
+
+fn number(src: &str, options: LexerOptions) -> Result<f64, &str> {
+    let mut lexer = Lexer::with_options(src, options);
+    let token = lexer.next_token();
+    assert_eq!(lexer.next_token().kind, TokenKind::Eof, "{}", src);
+    match token.kind {
+        TokenKind::Number(num) => Ok(num),
+        TokenKind::Error => Err(token.lexeme),
+        kind => panic!("{} lexed as {:?}", src, kind),
+    }
+}
+
+#[test]
+fn numbers_follow_the_lox_rules_by_default() {
+    assert_eq!(number("123", LexerOptions::default()), Ok(123.0));
+    assert_eq!(number("1.5", LexerOptions::default()), Ok(1.5));
+    // a trailing dot isn't part of the number
+    assert_eq!(kinds("1."), [TokenKind::Number(1.0), TokenKind::Dot]);
+    assert_eq!(
+        kinds("1_000"),
+        [
+            TokenKind::Number(1.0),
+            TokenKind::Ident(Symbol::intern("_000"))
+        ]
+    );
+    assert_eq!(
+        kinds("0x1"),
+        [
+            TokenKind::Number(0.0),
+            TokenKind::Ident(Symbol::intern("x1"))
+        ]
+    );
+}
+
+#[test]
+fn extended_numbers_accept_radixes_separators_and_exponents() {
+    let cases = [
+        ("1_000_000", 1_000_000.0),
+        ("1_0.2_5", 10.25),
+        ("0xff", 255.0),
+        ("0XdEaD_bEeF", 3_735_928_559.0),
+        ("0b1010_1010", 170.0),
+        ("1e3", 1000.0),
+        ("2.5E-1_0", 2.5e-10),
+        ("1e+2", 100.0),
+    ];
+    for (src, value) in cases {
+        assert_eq!(number(src, extended()), Ok(value), "{}", src);
+    }
+}
+
+#[test]
+fn separators_have_to_sit_between_digits() {
+    for src in ["1_", "1__0", "1_.5", "0x_1", "0b1_", "1_e5"] {
+        let tokens: Vec<Token> = Lexer::with_options(src, extended()).collect();
+        assert_eq!(tokens[0].kind, TokenKind::Error, "{}", src);
+        assert_eq!(
+            tokens[0].lexeme, "Digit separators must be between two digits.",
+            "{}",
+            src
+        );
+    }
+    assert_eq!(number("0x", extended()), Err("Invalid number literal."));
+    assert_eq!(
+        number("0x1_0000_0000_0000_0000", extended()),
+        Err("Number literal is too large.")
+    );
+}
+
+fn identifiers(src: &str, options: LexerOptions) -> Vec<String> {
+    Lexer::with_options(src, options)
+        .map(|token| match token.kind {
+            TokenKind::Ident(name) => name.to_string(),
+            TokenKind::Error => format!("error: {}", &src[token.span.start..token.span.end]),
+            kind => format!("{:?}", kind),
+        })
+        .collect()
+}
+
+#[test]
+fn identifiers_are_ascii_by_default() {
+    let ascii = LexerOptions::default();
+    assert_eq!(
+        identifiers("_private my_var x1 __", ascii),
+        ["_private", "my_var", "x1", "__"]
+    );
+    assert_eq!(identifiers("1x", ascii), ["Number(1.0)", "x"]);
+    assert_eq!(identifiers("café", ascii), ["caf", "error: é"]);
+}
+
+#[test]
+fn unicode_identifiers_follow_xid() {
+    assert_eq!(
+        identifiers("café 日本語 _ü Δx a١", unicode()),
+        ["café", "日本語", "_ü", "Δx", "a١"]
+    );
+    // a combining accent continues an identifier but can't start one
+    assert_eq!(identifiers("e\u{301}", unicode()), ["e\u{301}"]);
+    assert_eq!(identifiers("\u{301}e", unicode()), ["error: \u{301}", "e"]);
+    // digits from any script continue but never start one
+    assert_eq!(identifiers("١a", unicode()), ["error: ١", "a"]);
+    assert_eq!(identifiers("a🦀", unicode()), ["a", "error: 🦀"]);
+    // keywords are still keywords
+    assert_eq!(identifiers("var", unicode()), ["Var"]);
+}
+
+fn interpolating() -> LexerOptions {
+    LexerOptions {
+        string_interpolation: true,
+        ..LexerOptions::default()
+    }
+}
+
+#[test]
+fn string_values_leave_out_the_quotes_and_apply_escapes() {
+    let src = r#""tab\there" "quote\" back\\slash" "\u{48}\u{1F980}" "new\nline" "plain""#;
+    let tokens: Vec<Token> = Lexer::new(src).collect();
+    let values: Vec<String> = tokens.iter().map(|t| t.value().into_owned()).collect();
+    assert_eq!(
+        values,
+        [
+            "tab\there",
+            "quote\" back\\slash",
+            "H🦀",
+            "new\nline",
+            "plain"
+        ]
+    );
+    assert_eq!(tokens[0].lexeme, r"tab\there");
+    assert_eq!(spans(src, &tokens)[1], r#""quote\" back\\slash""#);
+    // strings without escapes aren't copied
+    assert!(matches!(tokens[4].value(), std::borrow::Cow::Borrowed(_)));
+}
+
+#[test]
+fn invalid_escapes_point_at_the_escape() {
+    let cases = [
+        (r#""a\qb""#, r"\q", "Invalid escape sequence."),
+        (r#""\u41""#, r"\u", "Invalid unicode escape sequence."),
+        (r#""\u{41""#, r"\u{41", "Invalid unicode escape sequence."),
+        (r#""\u{}""#, r"\u{}", "Invalid unicode escape sequence."),
+        (
+            r#""\u{D800}""#,
+            r"\u{D800}",
+            "Invalid unicode escape sequence.",
+        ),
+        (
+            r#""\u{1234567}""#,
+            r"\u{1234567}",
+            "Invalid unicode escape sequence.",
+        ),
+        (r#""ok \x then \y""#, r"\x", "Invalid escape sequence."),
+    ];
+    for (src, escape, message) in cases {
+        let tokens: Vec<Token> = Lexer::new(src).collect();
+        assert_eq!(tokens.len(), 1, "{}", src);
+        assert_eq!(tokens[0].kind, TokenKind::Error, "{}", src);
+        assert_eq!(tokens[0].lexeme, message, "{}", src);
+        assert_eq!(spans(src, &tokens), [escape], "{}", src);
+        assert_eq!(tokens[0].column as usize, src.find('\\').unwrap() + 1);
+    }
+
+    // the rest of the string is skipped, so lexing carries on after it
+    let tokens: Vec<Token> = Lexer::new("\"a\\q\" ;").collect();
+    assert_eq!(tokens[1].kind, TokenKind::Semicolon);
+}
+
+#[test]
+fn interpolations_split_strings_around_expressions() {
+    let src = r#""Hello ${name}, ${ { "nested ${1}" } }!""#;
+    let tokens: Vec<(TokenKind, &str)> = Lexer::with_options(src, interpolating())
+        .map(|token| (token.kind, token.lexeme))
+        .collect();
+    assert_eq!(
+        tokens,
+        [
+            (TokenKind::Interpolation, "Hello "),
+            (TokenKind::Ident(Symbol::intern("name")), "name"),
+            (TokenKind::Interpolation, ", "),
+            (TokenKind::LBrace, "{"),
+            (TokenKind::Interpolation, "nested "),
+            (TokenKind::Number(1.0), "1"),
+            (TokenKind::String, ""),
+            (TokenKind::RBrace, "}"),
+            (TokenKind::String, "!"),
+        ]
+    );
+
+    // without the option `${` is just text
+    assert_eq!(kinds(r#""${a}""#), [TokenKind::String]);
+    // and with it, an escaped `$` is too
+    assert_eq!(
+        Lexer::with_options(r#""\${a}""#, interpolating())
+            .map(|token| token.kind)
+            .collect::<Vec<_>>(),
+        [TokenKind::String]
+    );
+}
+
+#[test]
+fn interpolations_desugar_to_concatenation() {
+    let src = r#"print "a${b}c${"d"}";"#;
+    let program = Parser::with_options(src, interpolating())
+        .parse_program()
+        .unwrap();
+    let printed: Vec<String> = program
+        .iter()
+        .map(|statement| Sexpr(statement).to_string())
+        .collect();
+    assert_eq!(
+        printed,
+        [r#"(print (+ (+ (+ "a" (str b)) "c") (str "d")))"#]
+    );
+}
+
+#[test]
+fn interpolated_values_are_formatted_like_print() {
+    let src = r#"class P {} var p = P();
+        var s = "n = ${1}, ${nil}, ${p}, ${1 + 2.5}, ${"a" + "b"}";"#;
+    let mut program = Parser::with_options(src, interpolating())
+        .parse_program()
+        .unwrap();
+    Resolver::default().resolve(&mut program).unwrap();
+    let mut interpreter = Interpreter::new();
+    interpreter.execute(&program).unwrap();
+    let s = interpreter
+        .globals()
+        .find(|(name, _)| name.as_str() == "s")
+        .map(|(_, value)| value.to_string());
+    assert_eq!(s, Some("n = 1, nil, P instance, 3.5, ab".to_string()));
+}
+
+// every error with the text it covers
+fn errors(src: &str) -> Vec<(&str, &str)> {
+    Lexer::with_options(src, interpolating())
+        .filter(|token| token.kind == TokenKind::Error)
+        .map(|token| (token.lexeme, &src[token.span.start..token.span.end]))
+        .collect()
+}
+
+#[test]
+fn unterminated_interpolations_are_errors() {
+    assert_eq!(
+        errors(r#""a ${b"#),
+        [("Unterminated string interpolation.", "")]
+    );
+    assert_eq!(
+        errors(r#""a ${ "b ${c"#),
+        [("Unterminated string interpolation.", "")]
+    );
+    // the string carrying on after the `}` is what's unterminated here
+    assert_eq!(errors(r#""a ${b} c"#), [("Unterminated string.", "} c")]);
+
+    let program = Parser::with_options(r#"print "a ${b";"#, interpolating()).parse_program();
+    assert!(program.is_err());
+}
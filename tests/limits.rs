@@ -0,0 +1,161 @@
+use std::time::Duration;
+
+use blox::ast::Statement;
+use blox::interpreter::{Interpreter, RuntimeError};
+use blox::limits::Limits;
+use blox::parser::Parser;
+use blox::resolver::Resolver;
+
+fn program(src: &str) -> Vec<Statement> {
+    let mut program = Parser::new(src).parse_program().unwrap();
+    Resolver::default().resolve(&mut program).unwrap();
+    program
+}
+
+fn run(limits: Limits, src: &str) -> Result<(), RuntimeError> {
+    Interpreter::with_limits(limits).execute(&program(src))
+}
+
+#[test]
+fn fuel_stops_infinite_loops() {
+    let limits = Limits {
+        fuel: Some(1000),
+        ..Limits::default()
+    };
+    assert_eq!(run(limits, "while (true) {}"), Err(RuntimeError::OutOfFuel));
+    let calls = Limits {
+        fuel: Some(100),
+        ..Limits::default()
+    };
+    assert_eq!(
+        run(calls, "fun f() { f(); } f();"),
+        Err(RuntimeError::OutOfFuel)
+    );
+    assert_eq!(
+        run(limits, "for (var i = 0; i < 100; i = i + 1) {}"),
+        Ok(())
+    );
+}
+
+#[test]
+fn deadline_stops_infinite_loops() {
+    let limits = Limits {
+        timeout: Some(Duration::from_millis(50)),
+        ..Limits::default()
+    };
+    assert_eq!(run(limits, "while (true) {}"), Err(RuntimeError::Timeout));
+}
+
+#[test]
+fn heap_cap_stops_runaway_allocation() {
+    let limits = Limits {
+        max_allocated: Some(1 << 20),
+        ..Limits::default()
+    };
+    assert_eq!(
+        run(limits, "var s = \"ab\"; while (true) s = s + s;"),
+        Err(RuntimeError::OutOfMemory)
+    );
+    // dropping a long list recurses once per node, so keep it short
+    let list = Limits {
+        max_allocated: Some(1 << 16),
+        ..Limits::default()
+    };
+    assert_eq!(
+        run(
+            list,
+            "class Node {} var list = nil; while (true) { var node = Node(); node.next = list; list = node; }"
+        ),
+        Err(RuntimeError::OutOfMemory)
+    );
+    assert_eq!(run(limits, "var s = \"a\" + \"b\";"), Ok(()));
+}
+
+#[test]
+fn call_depth_stops_deep_recursion() {
+    let limits = Limits {
+        max_call_depth: Some(64),
+        ..Limits::default()
+    };
+    let count = "fun count(n) { if (n > 0) count(n - 1); }";
+    assert_eq!(run(limits, &format!("{} count(63);", count)), Ok(()));
+    assert_eq!(
+        run(limits, &format!("{} count(64);", count)),
+        Err(RuntimeError::StackOverflow)
+    );
+    assert_eq!(RuntimeError::StackOverflow.to_string(), "Stack overflow.");
+}
+
+#[test]
+fn every_run_gets_the_full_budget() {
+    let mut interpreter = Interpreter::with_limits(Limits {
+        fuel: Some(150),
+        ..Limits::default()
+    });
+    let counting = program("for (var i = 0; i < 100; i = i + 1) {}");
+    assert_eq!(interpreter.execute(&counting), Ok(()));
+    assert_eq!(interpreter.execute(&counting), Ok(()));
+
+    // and a run that went over doesn't leave the interpreter stuck
+    let call_depth = Limits {
+        max_call_depth: Some(8),
+        ..Limits::default()
+    };
+    let mut interpreter = Interpreter::with_limits(call_depth);
+    let recurse = program("fun f() { f(); } f();");
+    assert_eq!(
+        interpreter.execute(&recurse),
+        Err(RuntimeError::StackOverflow)
+    );
+    assert_eq!(interpreter.execute(&program("fun g() {} g();")), Ok(()));
+}
+
+#[test]
+fn deep_recursion_is_a_stack_overflow_by_default() {
+    let recurse = "fun f() { f(); } f();";
+    assert_eq!(
+        Interpreter::new().execute(&program(recurse)),
+        Err(RuntimeError::StackOverflow)
+    );
+
+    // the guard goes by the stack the thread actually has
+    let small = std::thread::Builder::new()
+        .stack_size(512 * 1024)
+        .spawn(move || Interpreter::new().execute(&program(recurse)))
+        .unwrap();
+    assert_eq!(small.join().unwrap(), Err(RuntimeError::StackOverflow));
+}
+
+#[test]
+fn max_stack_caps_recursion_below_the_thread_stack() {
+    let limits = Limits {
+        max_stack: Some(256 * 1024),
+        ..Limits::default()
+    };
+    let count = "fun count(n) { if (n > 0) return count(n - 1); return n; }";
+    assert_eq!(run(limits, &format!("{} count(5);", count)), Ok(()));
+    assert_eq!(
+        run(limits, &format!("{} count(1000);", count)),
+        Err(RuntimeError::StackOverflow)
+    );
+}
+
+#[test]
+fn stack_guard_only_stops_runaway_recursion() {
+    // a debug build needs about 12 MiB for 1000 calls, more than the
+    // default for test threads
+    let deep = std::thread::Builder::new()
+        .stack_size(32 << 20)
+        .spawn(|| {
+            let count = "fun count(n) { if (n > 0) return count(n - 1); return n; }";
+            let deep = program(&format!("{} print count(1000);", count));
+            let runaway = program("fun f() { f(); } f();");
+            let mut interpreter = Interpreter::new();
+            (interpreter.execute(&deep), interpreter.execute(&runaway))
+        })
+        .unwrap();
+    assert_eq!(
+        deep.join().unwrap(),
+        (Ok(()), Err(RuntimeError::StackOverflow))
+    );
+}
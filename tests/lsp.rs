@@ -0,0 +1,402 @@
+#![cfg(feature = "lsp")]
+
+use std::thread::{self, JoinHandle};
+
+use lsp_server::{Connection, Message, Notification, Request, RequestId};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidOpenTextDocument, Exit, Initialized,
+    Notification as NotificationTrait, PublishDiagnostics,
+};
+use lsp_types::request::{
+    DocumentSymbolRequest, GotoDefinition, HoverRequest, Initialize, Rename,
+    Request as RequestTrait, SemanticTokensFullRequest, Shutdown,
+};
+use lsp_types::{
+    DidChangeTextDocumentParams, DidOpenTextDocumentParams, DocumentSymbolParams,
+    DocumentSymbolResponse, GotoDefinitionParams, GotoDefinitionResponse, HoverContents,
+    HoverParams, InitializeParams, InitializedParams, Position, PublishDiagnosticsParams, Range,
+    RenameParams, SemanticTokensParams, SemanticTokensResult, TextDocumentContentChangeEvent,
+    TextDocumentIdentifier, TextDocumentItem, TextDocumentPositionParams, Url,
+    VersionedTextDocumentIdentifier,
+};
+
+const PROGRAM: &str = r#"var greeting = "hi";
+fun greet(name) {
+    print greeting + name;
+}
+class Point {
+    init(x) {
+        this.x = x;
+    }
+}
+var s = "😀"; greet(s);
+"#;
+
+// Plays the editor's side of the protocol against a server on another thread
+struct Client {
+    connection: Connection,
+    server: JoinHandle<()>,
+    next_id: i32,
+    uri: Url,
+}
+
+impl Client {
+    fn start() -> Client {
+        let (server, connection) = Connection::memory();
+        let server = thread::spawn(move || blox::lsp::run(server).unwrap());
+        let mut client = Client {
+            connection,
+            server,
+            next_id: 0,
+            uri: Url::parse("file:///test.lox").unwrap(),
+        };
+        client
+            .request::<Initialize>(InitializeParams::default())
+            .unwrap();
+        client.notify::<Initialized>(InitializedParams {});
+        client
+    }
+
+    fn stop(mut self) {
+        self.request::<Shutdown>(()).unwrap();
+        self.notify::<Exit>(());
+        self.server.join().unwrap();
+    }
+
+    fn request<R: RequestTrait>(&mut self, params: R::Params) -> Result<R::Result, String> {
+        self.next_id += 1;
+        let id = RequestId::from(self.next_id);
+        let request = Request::new(id.clone(), R::METHOD.to_string(), params);
+        self.connection.sender.send(request.into()).unwrap();
+
+        loop {
+            match self.connection.receiver.recv().unwrap() {
+                Message::Response(response) if response.id == id => {
+                    return match response.error {
+                        Some(error) => Err(error.message),
+                        None => Ok(serde_json::from_value(response.result.unwrap()).unwrap()),
+                    };
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn notify<N: NotificationTrait>(&self, params: N::Params) {
+        let notification = Notification::new(N::METHOD.to_string(), params);
+        self.connection.sender.send(notification.into()).unwrap();
+    }
+
+    fn diagnostics(&self) -> PublishDiagnosticsParams {
+        loop {
+            if let Message::Notification(notification) = self.connection.receiver.recv().unwrap() {
+                if notification.method == PublishDiagnostics::METHOD {
+                    return serde_json::from_value(notification.params).unwrap();
+                }
+            }
+        }
+    }
+
+    fn open(&self, text: &str) -> PublishDiagnosticsParams {
+        self.notify::<DidOpenTextDocument>(DidOpenTextDocumentParams {
+            text_document: TextDocumentItem::new(
+                self.uri.clone(),
+                "lox".to_string(),
+                1,
+                text.to_string(),
+            ),
+        });
+        self.diagnostics()
+    }
+
+    fn at(&self, line: u32, character: u32) -> TextDocumentPositionParams {
+        TextDocumentPositionParams::new(
+            TextDocumentIdentifier::new(self.uri.clone()),
+            Position::new(line, character),
+        )
+    }
+}
+
+fn range(line: u32, start: u32, end: u32) -> Range {
+    Range::new(Position::new(line, start), Position::new(line, end))
+}
+
+#[test]
+fn publishes_diagnostics_on_open_and_change() {
+    let client = Client::start();
+
+    let diagnostics = client.open("print 1 +;\n");
+    assert_eq!(diagnostics.version, Some(1));
+    let messages: Vec<_> = diagnostics
+        .diagnostics
+        .iter()
+        .map(|d| (d.range, d.message.as_str()))
+        .collect();
+    assert_eq!(messages, [(range(0, 9, 10), "Expect expression.")]);
+
+    client.notify::<DidChangeTextDocument>(DidChangeTextDocumentParams {
+        text_document: VersionedTextDocumentIdentifier::new(client.uri.clone(), 2),
+        content_changes: vec![TextDocumentContentChangeEvent {
+            range: None,
+            range_length: None,
+            text: "{\n    var a = a;\n}\nreturn 1;\n".to_string(),
+        }],
+    });
+    let diagnostics = client.diagnostics();
+    assert_eq!(diagnostics.version, Some(2));
+    let messages: Vec<_> = diagnostics
+        .diagnostics
+        .iter()
+        .map(|d| (d.range, d.message.as_str()))
+        .collect();
+    assert_eq!(
+        messages,
+        [
+            (
+                range(1, 12, 13),
+                "Can't read local variable in its own initializer."
+            ),
+            (range(3, 0, 6), "Can't return from top-level code."),
+        ]
+    );
+
+    client.stop();
+}
+
+#[test]
+fn keeps_working_past_syntax_errors_and_bad_notifications() {
+    let mut client = Client::start();
+
+    // params that don't fit the method are dropped, the server carries on
+    let notification = Notification::new(DidOpenTextDocument::METHOD.to_string(), "text");
+    client.connection.sender.send(notification.into()).unwrap();
+
+    let diagnostics =
+        client.open("fun f(a) {\n    print a +;\n    return a;\n}\nvar b = ;\nprint f(1);\n");
+    let messages: Vec<_> = diagnostics
+        .diagnostics
+        .iter()
+        .map(|d| (d.range, d.message.as_str()))
+        .collect();
+    assert_eq!(
+        messages,
+        [
+            (range(1, 13, 14), "Expect expression."),
+            (range(4, 8, 9), "Expect expression.")
+        ]
+    );
+
+    // everything around the errors was still parsed
+    let definition = client
+        .request::<GotoDefinition>(GotoDefinitionParams {
+            text_document_position_params: client.at(5, 6),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        })
+        .unwrap();
+    assert_eq!(
+        definition,
+        Some(GotoDefinitionResponse::Scalar(lsp_types::Location::new(
+            client.uri.clone(),
+            range(0, 4, 5)
+        )))
+    );
+    let definition = client
+        .request::<GotoDefinition>(GotoDefinitionParams {
+            text_document_position_params: client.at(2, 11),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        })
+        .unwrap();
+    assert_eq!(
+        definition,
+        Some(GotoDefinitionResponse::Scalar(lsp_types::Location::new(
+            client.uri.clone(),
+            range(0, 6, 7)
+        )))
+    );
+
+    client.stop();
+}
+
+#[test]
+fn navigates_between_uses_and_definitions() {
+    let mut client = Client::start();
+    assert!(client.open(PROGRAM).diagnostics.is_empty());
+
+    // `name` in the print goes to the parameter
+    let definition = client
+        .request::<GotoDefinition>(GotoDefinitionParams {
+            text_document_position_params: client.at(2, 22),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        })
+        .unwrap();
+    assert_eq!(
+        definition,
+        Some(GotoDefinitionResponse::Scalar(lsp_types::Location::new(
+            client.uri.clone(),
+            range(1, 10, 14)
+        )))
+    );
+
+    // columns count UTF-16 code units, the emoji takes two
+    let hover = client
+        .request::<HoverRequest>(HoverParams {
+            text_document_position_params: client.at(9, 20),
+            work_done_progress_params: Default::default(),
+        })
+        .unwrap()
+        .unwrap();
+    assert_eq!(hover.range, Some(range(9, 20, 21)));
+    match hover.contents {
+        HoverContents::Markup(markup) => assert_eq!(markup.value, "```lox\nvar s\n```"),
+        contents => panic!("unexpected hover {:?}", contents),
+    }
+
+    let hover = client
+        .request::<HoverRequest>(HoverParams {
+            text_document_position_params: client.at(9, 15),
+            work_done_progress_params: Default::default(),
+        })
+        .unwrap()
+        .unwrap();
+    match hover.contents {
+        HoverContents::Markup(markup) => {
+            assert_eq!(markup.value, "```lox\nfun greet(name)\n```")
+        }
+        contents => panic!("unexpected hover {:?}", contents),
+    }
+
+    let symbols = client
+        .request::<DocumentSymbolRequest>(DocumentSymbolParams {
+            text_document: TextDocumentIdentifier::new(client.uri.clone()),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        })
+        .unwrap();
+    let symbols = match symbols {
+        Some(DocumentSymbolResponse::Nested(symbols)) => symbols,
+        symbols => panic!("unexpected symbols {:?}", symbols),
+    };
+    let names: Vec<_> = symbols.iter().map(|s| s.name.as_str()).collect();
+    assert_eq!(names, ["greeting", "greet", "Point", "s"]);
+    let methods: Vec<_> = symbols[2]
+        .children
+        .iter()
+        .flatten()
+        .map(|s| s.detail.as_deref().unwrap())
+        .collect();
+    assert_eq!(methods, ["init(x)"]);
+
+    client.stop();
+}
+
+#[test]
+fn renames_every_occurrence() {
+    let mut client = Client::start();
+    client.open(PROGRAM);
+
+    let rename = |client: &mut Client, new_name: &str| {
+        client.request::<Rename>(RenameParams {
+            text_document_position: client.at(0, 6),
+            new_name: new_name.to_string(),
+            work_done_progress_params: Default::default(),
+        })
+    };
+
+    let edit = rename(&mut client, "salutation").unwrap().unwrap();
+    let edits = &edit.changes.unwrap()[&client.uri];
+    let ranges: Vec<_> = edits.iter().map(|e| e.range).collect();
+    assert_eq!(ranges, [range(0, 4, 12), range(2, 10, 18)]);
+    assert!(edits.iter().all(|e| e.new_text == "salutation"));
+
+    assert!(rename(&mut client, "while").is_err());
+    assert!(rename(&mut client, "two words").is_err());
+
+    // `this` has nothing to rename
+    let this = client.request::<Rename>(RenameParams {
+        text_document_position: client.at(6, 9),
+        new_name: "self".to_string(),
+        work_done_progress_params: Default::default(),
+    });
+    assert_eq!(this, Err("Can't rename 'this'.".to_string()));
+
+    // calls to methods aren't linked to them, so they'd keep the old name
+    let method = client.request::<Rename>(RenameParams {
+        text_document_position: client.at(5, 5),
+        new_name: "new".to_string(),
+        work_done_progress_params: Default::default(),
+    });
+    assert_eq!(method, Err("Can't rename methods.".to_string()));
+
+    // another global in the same scope, and a parameter that would capture
+    // the global used next to it
+    assert_eq!(
+        rename(&mut client, "s"),
+        Err("'s' would clash with another name.".to_string())
+    );
+    let parameter = client.request::<Rename>(RenameParams {
+        text_document_position: client.at(1, 11),
+        new_name: "greeting".to_string(),
+        work_done_progress_params: Default::default(),
+    });
+    assert_eq!(
+        parameter,
+        Err("'greeting' would clash with another name.".to_string())
+    );
+
+    client.stop();
+}
+
+#[test]
+fn classifies_semantic_tokens() {
+    let mut client = Client::start();
+    client.open("var a = 1;\nfun f(b) { print a + b; }\n");
+
+    let tokens = match client
+        .request::<SemanticTokensFullRequest>(SemanticTokensParams {
+            text_document: TextDocumentIdentifier::new(client.uri.clone()),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        })
+        .unwrap()
+    {
+        Some(SemanticTokensResult::Tokens(tokens)) => tokens.data,
+        tokens => panic!("unexpected tokens {:?}", tokens),
+    };
+
+    // (delta line, delta start, length, legend index, modifiers), with the
+    // legend keyword, variable, parameter, function, method, class, property,
+    // string, number, operator, comment
+    let data: Vec<_> = tokens
+        .iter()
+        .map(|t| {
+            (
+                t.delta_line,
+                t.delta_start,
+                t.length,
+                t.token_type,
+                t.token_modifiers_bitset,
+            )
+        })
+        .collect();
+    assert_eq!(
+        data,
+        [
+            (0, 0, 3, 0, 0), // var
+            (0, 4, 1, 1, 1), // a
+            (0, 2, 1, 9, 0), // =
+            (0, 2, 1, 8, 0), // 1
+            (1, 0, 3, 0, 0), // fun
+            (0, 4, 1, 3, 1), // f
+            (0, 2, 1, 2, 1), // b
+            (0, 5, 5, 0, 0), // print
+            (0, 6, 1, 1, 0), // a
+            (0, 2, 1, 9, 0), // +
+            (0, 2, 1, 2, 0), // b
+        ]
+    );
+
+    client.stop();
+}
@@ -0,0 +1,125 @@
+use blox::interpreter::Interpreter;
+use blox::optimizer::optimize;
+use blox::parser::Parser;
+use blox::resolver::Resolver;
+use blox::sexpr::Sexpr;
+
+fn optimized(src: &str) -> Vec<String> {
+    let mut program = Parser::new(src).parse_program().unwrap();
+    Resolver::default().resolve(&mut program).unwrap();
+    optimize(program)
+        .iter()
+        .map(|s| Sexpr(s).to_string())
+        .collect()
+}
+
+#[test]
+fn folds_every_operator_on_literals() {
+    let cases = [
+        ("1 + 2", "3"),
+        ("7 - 10", "-3"),
+        ("2 * 4", "8"),
+        ("1 / 4", "0.25"),
+        ("-(3)", "-3"),
+        ("!nil", "true"),
+        ("\"a\" + \"b\"", "\"ab\""),
+        ("1 < 2", "true"),
+        ("1 <= 1", "true"),
+        ("1 > 2", "false"),
+        ("2 >= 3", "false"),
+        ("1 == 1", "true"),
+        ("nil != false", "true"),
+        ("(1 + 2) * (3 + 4)", "21"),
+        ("nil and 1", "nil"),
+        ("1 and \"a\"", "\"a\""),
+        ("false or 2", "2"),
+        ("0 or 2", "0"),
+    ];
+    for (expression, folded) in cases {
+        assert_eq!(
+            optimized(&format!("print {};", expression)),
+            [format!("(print {})", folded)],
+            "{}",
+            expression
+        );
+    }
+}
+
+#[test]
+fn leaves_anything_that_could_fail_or_has_side_effects() {
+    // these raise runtime errors, which have to happen when the code runs
+    assert_eq!(optimized("print 1 + \"a\";"), ["(print (+ 1 \"a\"))"]);
+    assert_eq!(optimized("print -\"a\";"), ["(print (- \"a\"))"]);
+
+    // only the literal parts of expressions with calls and assignments fold
+    assert_eq!(
+        optimized("fun f() { return 1; } var a; print f() + (2 * 3); a = 1 + 1;"),
+        [
+            "(fun f () (return 1))",
+            "(var a)",
+            "(print (+ (call f) 6))",
+            "(expr (assign a 2))"
+        ]
+    );
+    assert_eq!(
+        optimized("var a = 1; print a + 1 * 2;"),
+        ["(var a 1)", "(print (+ a 2))"]
+    );
+
+    // a literal on the left of and/or decides which operand is the result,
+    // one on the right doesn't
+    assert_eq!(
+        optimized("var a; print true and a; print nil or a; print a or true;"),
+        ["(var a)", "(print a)", "(print a)", "(print (or a true))"]
+    );
+    assert_eq!(
+        optimized("fun f() {} print false and f(); print 1 or f();"),
+        ["(fun f ())", "(print false)", "(print 1)"]
+    );
+}
+
+#[test]
+fn drops_branches_that_never_run() {
+    assert_eq!(
+        optimized("if (false) print 1; else print 2;"),
+        ["(print 2)"]
+    );
+    assert_eq!(optimized("if (1 > 2) print 1;"), Vec::<String>::new());
+    assert_eq!(optimized("if (\"\") print 1; else print 2;"), ["(print 1)"]);
+    // the condition may still have to run for its side effects
+    assert_eq!(
+        optimized("var a; if (a = nil) print 1;"),
+        ["(var a)", "(if (assign a nil) (print 1))"]
+    );
+    // an emptied then branch is kept so there is somewhere to go
+    assert_eq!(
+        optimized("var a; if (a) { if (false) print 1; } else print 2;"),
+        ["(var a)", "(if a (block) (print 2))"]
+    );
+    assert_eq!(
+        optimized("while (true) if (false) print 1;"),
+        ["(while true (block))"]
+    );
+}
+
+#[test]
+fn optimized_programs_run_the_same() {
+    let src = "var n = 0; fun f() { n = n + 1; return n; } \
+               if (1 < 2) n = f() + 2 * 3; else n = -1; print n;";
+    let mut program = Parser::new(src).parse_program().unwrap();
+    Resolver::default().resolve(&mut program).unwrap();
+
+    let mut plain = Interpreter::new();
+    plain.execute(&program).unwrap();
+    let mut optimized = Interpreter::new();
+    optimized.execute(&optimize(program)).unwrap();
+
+    let n = |interpreter: &Interpreter| {
+        interpreter
+            .globals()
+            .find(|(name, _)| name.as_str() == "n")
+            .map(|(_, value)| value.to_string())
+    };
+    assert_eq!(n(&plain), Some("7".to_string()));
+    assert_eq!(n(&optimized), Some("7".to_string()));
+}
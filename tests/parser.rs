@@ -0,0 +1,449 @@
+use blox::ast::{Expression, Variable};
+use blox::interpreter::{Interpreter, RuntimeError, Value};
+use blox::lexer::TokenKind;
+use blox::parser::{ParseRule, Parser, Precedence};
+use blox::sexpr::Sexpr;
+use blox::symbol::Symbol;
+use proptest::prelude::*;
+
+// Binding power of the operator at the root of an expression, mirroring the
+// parser's Precedence levels
+fn precedence(expression: &Expression) -> u8 {
+    match expression {
+        Expression::Binary(_, operator, _) => match operator {
+            TokenKind::Or => 1,
+            TokenKind::And => 2,
+            TokenKind::IsEqual | TokenKind::NotBang => 3,
+            TokenKind::Greater
+            | TokenKind::GreaterEqual
+            | TokenKind::Less
+            | TokenKind::LessEqual => 4,
+            TokenKind::Plus | TokenKind::Minus => 5,
+            _ => 6,
+        },
+        Expression::Unary(..) => 7,
+        _ => 8,
+    }
+}
+
+fn operator(kind: &TokenKind) -> &str {
+    match kind {
+        TokenKind::Plus => "+",
+        TokenKind::Minus => "-",
+        TokenKind::Star => "*",
+        TokenKind::Slash => "/",
+        TokenKind::Bang => "!",
+        TokenKind::IsEqual => "==",
+        TokenKind::NotBang => "!=",
+        TokenKind::Greater => ">",
+        TokenKind::GreaterEqual => ">=",
+        TokenKind::Less => "<",
+        TokenKind::LessEqual => "<=",
+        TokenKind::And => "and",
+        TokenKind::Or => "or",
+        TokenKind::Operator(operator) => operator.as_str(),
+        kind => panic!("no operator for {:?}", kind),
+    }
+}
+
+fn atom(expression: &Expression) -> String {
+    match expression {
+        Expression::Number(num) => format!("{}", num),
+        Expression::String(s) => format!("\"{}\"", s),
+        Expression::Bool(b) => format!("{}", b),
+        Expression::Nil => "nil".to_string(),
+        Expression::Variable(variable) => variable.name.to_string(),
+        expression => panic!("not an atom {:?}", expression),
+    }
+}
+
+// Source text with only the parentheses precedence and left associativity
+// make necessary
+fn source(expression: &Expression) -> String {
+    let operand = |expression: &Expression, min: u8| match precedence(expression) >= min {
+        true => source(expression),
+        false => format!("({})", source(expression)),
+    };
+
+    match expression {
+        Expression::Binary(left, op, right) => {
+            let level = precedence(expression);
+            format!(
+                "{} {} {}",
+                operand(left, level),
+                operator(op),
+                operand(right, level + 1)
+            )
+        }
+        Expression::Unary(op, expression) => format!("{}{}", operator(op), operand(expression, 7)),
+        Expression::Grouping(expression) => format!("({})", source(expression)),
+        expression => atom(expression),
+    }
+}
+
+// Reference printer that spells out the tree structure, groupings are left
+// out since they only exist to shape the tree
+fn parenthesized(expression: &Expression) -> String {
+    match expression {
+        Expression::Binary(left, op, right) => format!(
+            "({} {} {})",
+            parenthesized(left),
+            operator(op),
+            parenthesized(right)
+        ),
+        Expression::Unary(op, expression) => {
+            format!("({} {})", operator(op), parenthesized(expression))
+        }
+        Expression::Grouping(expression) => parenthesized(expression),
+        expression => atom(expression),
+    }
+}
+
+fn parse(src: &str) -> Expression {
+    match Parser::new(src).parse() {
+        Ok(expression) => expression,
+        Err(e) => panic!("failed to parse {:?}: {:?}", src, e),
+    }
+}
+
+fn arb_expression() -> impl Strategy<Value = Expression> {
+    let leaf = prop_oneof![
+        (0u32..1000).prop_map(|num| Expression::Number(num as f64)),
+        "[a-z]{0,4}".prop_map(Expression::String),
+        any::<bool>().prop_map(Expression::Bool),
+        Just(Expression::Nil),
+        prop::sample::select(vec!["a", "b", "c"])
+            .prop_map(|name| Expression::Variable(Variable::new(Symbol::intern(name), 1))),
+    ];
+
+    let binary = prop::sample::select(vec![
+        TokenKind::Plus,
+        TokenKind::Minus,
+        TokenKind::Star,
+        TokenKind::Slash,
+        TokenKind::IsEqual,
+        TokenKind::NotBang,
+        TokenKind::Greater,
+        TokenKind::GreaterEqual,
+        TokenKind::Less,
+        TokenKind::LessEqual,
+        TokenKind::And,
+        TokenKind::Or,
+    ]);
+    let unary = prop::sample::select(vec![TokenKind::Minus, TokenKind::Bang]);
+
+    leaf.prop_recursive(6, 64, 2, move |inner| {
+        prop_oneof![
+            (inner.clone(), binary.clone(), inner.clone()).prop_map(|(left, op, right)| {
+                Expression::Binary(Box::new(left), op, Box::new(right))
+            }),
+            (unary.clone(), inner.clone())
+                .prop_map(|(op, expression)| Expression::Unary(op, Box::new(expression))),
+            inner.prop_map(|expression| Expression::Grouping(Box::new(expression))),
+        ]
+    })
+}
+
+proptest! {
+    #[test]
+    fn parses_minimally_parenthesized_source(expression in arb_expression()) {
+        let src = source(&expression);
+        prop_assert_eq!(parenthesized(&parse(&src)), parenthesized(&expression), "source: {}", src);
+    }
+
+    #[test]
+    fn display_round_trips(expression in arb_expression()) {
+        let src = expression.to_string();
+        prop_assert_eq!(parenthesized(&parse(&src)), parenthesized(&expression), "source: {}", src);
+    }
+}
+
+#[test]
+fn binary_operators_are_left_associative() {
+    assert_eq!(parenthesized(&parse("1 - 2 - 3")), "((1 - 2) - 3)");
+    assert_eq!(parenthesized(&parse("8 / 4 / 2")), "((8 / 4) / 2)");
+    assert_eq!(parenthesized(&parse("1 < 2 == true")), "((1 < 2) == true)");
+}
+
+#[test]
+fn or_binds_looser_than_and_which_binds_looser_than_equality() {
+    assert_eq!(
+        parenthesized(&parse("a or b and c or d")),
+        "((a or (b and c)) or d)"
+    );
+    assert_eq!(
+        parenthesized(&parse("a == b and !c or 1 < 2")),
+        "(((a == b) and (! c)) or (1 < 2))"
+    );
+}
+
+#[test]
+fn unary_binds_tighter_than_binary() {
+    assert_eq!(parenthesized(&parse("-1 + 2")), "((- 1) + 2)");
+    assert_eq!(parenthesized(&parse("!a == b")), "((! a) == b)");
+    assert_eq!(parenthesized(&parse("--1 * 2")), "((- (- 1)) * 2)");
+}
+
+fn parse_extended(src: &str) -> Expression {
+    let mut parser = Parser::new(src);
+    parser
+        .register_operator("%", ParseRule::infix(Precedence::Factor))
+        .unwrap();
+    parser
+        .register_operator("^", ParseRule::infix_right(Precedence::Unary))
+        .unwrap();
+    match parser.parse() {
+        Ok(expression) => expression,
+        Err(e) => panic!("failed to parse {:?}: {:?}", src, e),
+    }
+}
+
+#[test]
+fn registered_operators_follow_their_rules() {
+    assert_eq!(
+        parenthesized(&parse_extended("1 + 7 % 4 % 2")),
+        "(1 + ((7 % 4) % 2))"
+    );
+    assert_eq!(
+        parenthesized(&parse_extended("2 ^ 3 ^ 2 * 2")),
+        "((2 ^ (3 ^ 2)) * 2)"
+    );
+    assert_eq!(parenthesized(&parse_extended("2 * 3 ^ 2")), "(2 * (3 ^ 2))");
+}
+
+#[test]
+fn operators_that_would_replace_built_in_tokens_are_refused() {
+    let mut parser = Parser::new("a=-f(-1)<=!-2");
+    let rule = ParseRule::infix(Precedence::Factor);
+    // anything starting with a built in token, which would change how
+    // valid source is read
+    for spelling in ["-", "==", "<=", ".", "=-", "(-", "!-", "**", "<=>"] {
+        assert_eq!(
+            parser.register_operator(spelling, rule),
+            Err("Operators can't replace built in tokens."),
+            "{:?}",
+            spelling
+        );
+    }
+    for spelling in ["//", "/*", "+\""] {
+        assert_eq!(
+            parser.register_operator(spelling, rule),
+            Err("Operators can't start strings or comments."),
+            "{:?}",
+            spelling
+        );
+    }
+    for spelling in ["", "+a", "+ +"] {
+        assert_eq!(
+            parser.register_operator(spelling, rule),
+            Err("Operators must be made of punctuation."),
+            "{:?}",
+            spelling
+        );
+    }
+    assert_eq!(parser.register_operator("@", rule), Ok(()));
+    assert_eq!(parser.register_operator("|>", rule), Ok(()));
+
+    // nothing that was refused changes how the source is read
+    let expression = parser.parse().unwrap();
+    assert_eq!(
+        Sexpr(&expression).to_string(),
+        "(assign a (<= (- (call f (- 1))) (! (- 2))))"
+    );
+}
+
+#[test]
+fn registered_operators_evaluate_through_the_interpreter() {
+    let mut interpreter = Interpreter::new();
+    interpreter.define_binary_operator("%", |left, right| match (left, right) {
+        (Value::Number(left), Value::Number(right)) => Ok(Value::Number(left % right)),
+        _ => Err(RuntimeError::OperandsMustBeNumbers),
+    });
+    interpreter.define_binary_operator("^", |left, right| match (left, right) {
+        (Value::Number(left), Value::Number(right)) => Ok(Value::Number(left.powf(right))),
+        _ => Err(RuntimeError::OperandsMustBeNumbers),
+    });
+
+    let value = interpreter.evaluate(&parse_extended("1 + 2 ^ 3 ^ 2 % 10"));
+    assert_eq!(value, Ok(Value::Number(3.0)));
+}
+
+fn statements(src: &str) -> Result<Vec<String>, String> {
+    Parser::new(src)
+        .parse_program()
+        .map(|program| program.iter().map(|s| Sexpr(s).to_string()).collect())
+        .map_err(|e| format!("{:?}", e))
+}
+
+fn list(count: usize) -> String {
+    (0..count)
+        .map(|idx| format!("a{}", idx))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[test]
+fn calls_and_functions_take_at_most_255_arguments() {
+    assert!(statements(&format!("f({});", list(255))).is_ok());
+    assert_eq!(
+        statements(&format!("f({});", list(256))),
+        Err("[line 1] Error: Can't have more than 255 arguments.".to_string())
+    );
+    assert!(statements(&format!("fun f({}) {{}}", list(255))).is_ok());
+    assert_eq!(
+        statements(&format!("fun f({}) {{}}", list(256))),
+        Err("[line 1] Error: Can't have more than 255 parameters.".to_string())
+    );
+}
+
+#[test]
+fn only_variables_and_properties_can_be_assigned() {
+    for src in ["a + b = c;", "(a) = 1;", "1 = 2;", "a.b() = 1;", "!a = b;"] {
+        assert_eq!(
+            statements(src),
+            Err("[line 1] Error: Invalid assignment target.".to_string()),
+            "{}",
+            src
+        );
+    }
+}
+
+#[test]
+fn assignment_is_right_associative() {
+    assert_eq!(
+        statements("a = b = c;"),
+        Ok(vec!["(expr (assign a (assign b c)))".to_string()])
+    );
+    assert_eq!(
+        statements("a.b = c.d = e = 1 + 2;"),
+        Ok(vec![
+            "(expr (set a b (set c d (assign e (+ 1 2)))))".to_string()
+        ])
+    );
+    // the value takes in everything of lower precedence than assignment
+    assert_eq!(
+        statements("a = b == c;"),
+        Ok(vec!["(expr (assign a (== b c)))".to_string()])
+    );
+}
+
+#[test]
+fn calls_and_property_access_chain_left_to_right() {
+    assert_eq!(
+        statements("a.b(1)(2).c;"),
+        Ok(vec![
+            "(expr (get (call (call (get a b) 1) 2) c))".to_string()
+        ])
+    );
+    assert_eq!(
+        statements("-a.b();"),
+        Ok(vec!["(expr (- (call (get a b))))".to_string()])
+    );
+}
+
+fn recovered(src: &str) -> (Vec<String>, Vec<String>) {
+    let (program, errors) = Parser::new(src).parse_program_recovering();
+    (
+        program.iter().map(|s| Sexpr(s).to_string()).collect(),
+        errors
+            .iter()
+            .map(|(error, span)| format!("{}..{} {:?}", span.start, span.end, error))
+            .collect(),
+    )
+}
+
+#[test]
+fn recovering_skips_to_the_next_statement() {
+    let (program, errors) = recovered("var a = ; print 1; var = 2; print 3;");
+    assert_eq!(program, ["(print 1)", "(print 3)"]);
+    assert_eq!(
+        errors,
+        [
+            "8..9 unexpected prefix",
+            "23..24 [line 1] Error: Expect variable name."
+        ]
+    );
+
+    // the rest of a block survives an error in it, and braces opened by the
+    // broken statement are skipped with it
+    let (program, errors) = recovered("fun f() { if (a +) { print 1; } print 2; } print 3;");
+    assert_eq!(program, ["(fun f () (print 2))", "(print 3)"]);
+    assert_eq!(errors, ["17..18 unexpected prefix"]);
+
+    // a missing brace only costs the error
+    let (program, errors) = recovered("{ print 1;");
+    assert_eq!(program, ["(block (print 1))"]);
+    assert_eq!(errors.len(), 1);
+
+    // parse_program still stops at the first error
+    assert!(Parser::new("var a = ; print 1;").parse_program().is_err());
+}
+
+#[test]
+fn lexer_errors_are_parse_errors() {
+    // the parser steps over the error token, the program still fails
+    assert_eq!(
+        statements("print 1;\nprint 2 | 3;"),
+        Err("[line 2] Error: Unexpected character.".to_string())
+    );
+    assert_eq!(
+        Parser::new("\"open")
+            .parse()
+            .map_err(|e| format!("{:?}", e)),
+        Err("[line 1] Error: Unterminated string.".to_string())
+    );
+    // a parse error before the lexer error is the one reported
+    assert_eq!(
+        statements("var = 1; print |;"),
+        Err("[line 1] Error: Expect variable name.".to_string())
+    );
+
+    let (program, errors) = recovered("print 1 |; var = 3; print 4;");
+    assert_eq!(program, ["(print 1)", "(print 4)"]);
+    assert_eq!(
+        errors,
+        [
+            "8..9 [line 1] Error: Unexpected character.",
+            "15..16 [line 1] Error: Expect variable name."
+        ]
+    );
+}
+
+#[test]
+fn deep_nesting_is_an_error_not_a_crash() {
+    let too_deep = [
+        format!("{}1{};", "(".repeat(20_000), ")".repeat(20_000)),
+        format!("{}1;", "-".repeat(20_000)),
+        format!("{}{}", "{".repeat(20_000), "}".repeat(20_000)),
+        "if (true) ".repeat(20_000) + "print 1;",
+        "fun f() {".repeat(20_000) + &"}".repeat(20_000),
+    ];
+    for src in too_deep {
+        assert_eq!(
+            statements(&src),
+            Err("[line 1] Error: Too much nesting.".to_string())
+        );
+    }
+
+    let (program, errors) = recovered(&format!("print {}1;\nprint 2;", "-".repeat(20_000)));
+    assert_eq!(program, ["(print 2)"]);
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].ends_with("Error: Too much nesting."));
+
+    // nesting that real programs use is untouched
+    let src = format!("print {}1{};", "(".repeat(100), ")".repeat(100));
+    assert!(statements(&src).is_ok());
+}
+
+#[test]
+fn nesting_stops_short_of_the_threads_stack() {
+    // well within MAX_NESTING, but more than a thread this small can recurse
+    let src = format!("print {}1{};", "(".repeat(200), ")".repeat(200));
+    let result = std::thread::Builder::new()
+        .stack_size(160 * 1024)
+        .spawn(move || statements(&src))
+        .unwrap()
+        .join()
+        .unwrap();
+    assert_eq!(result, Err("[line 1] Error: Too much nesting.".to_string()));
+}
@@ -0,0 +1,98 @@
+use std::fs;
+
+use blox::repl::{is_incomplete, Session};
+use blox::tier::DEFAULT_JIT_THRESHOLD;
+
+#[test]
+fn waits_for_unbalanced_input() {
+    assert!(is_incomplete("fun f() {"));
+    assert!(is_incomplete("print (1 +"));
+    assert!(is_incomplete("print \"two\nlines"));
+    assert!(is_incomplete("/* still"));
+    assert!(!is_incomplete("fun f() {}"));
+    assert!(!is_incomplete("print 1 +"));
+    // too many closing brackets is an error to report, not more to read
+    assert!(!is_incomplete("}"));
+}
+
+#[test]
+fn keeps_globals_until_reset() {
+    let mut session = Session::new(DEFAULT_JIT_THRESHOLD);
+    assert_eq!(session.eval("var b = \"two\";\n"), Ok(String::new()));
+    assert_eq!(
+        session.eval("fun f() {\n    return b;\n}\n"),
+        Ok(String::new())
+    );
+    assert_eq!(session.eval("var a = 1;"), Ok(String::new()));
+    assert_eq!(
+        session.eval(":globals"),
+        Ok("a = 1\nb = two\nf = <fn f>".to_string())
+    );
+
+    assert_eq!(session.eval(":reset"), Ok(String::new()));
+    assert_eq!(session.eval(":globals"), Ok(String::new()));
+}
+
+#[test]
+fn runs_meta_commands() {
+    let mut session = Session::new(DEFAULT_JIT_THRESHOLD);
+    assert_eq!(
+        session.eval(":ast print 1 + 2 * x;"),
+        Ok("(print (+ 1 (* 2 x)))".to_string())
+    );
+    assert_eq!(session.eval(":ast -a"), Ok("(- a)".to_string()));
+    assert_eq!(session.eval(":ast -a;"), Ok("(expr (- a))".to_string()));
+
+    assert_eq!(
+        session.eval(":opt if (1 > 2) print 1; else print 2 * x;"),
+        Ok("(print (* 2 x))".to_string())
+    );
+
+    let clif = session.eval(":clif 1 + 2 * 3").unwrap();
+    assert!(clif.starts_with("function"), "{}", clif);
+    assert!(clif.contains("return"), "{}", clif);
+    assert!(session.eval(":clif \"a\" + \"b\"").is_err());
+
+    let time = session.eval(":time var a = 1;").unwrap();
+    assert!(time.starts_with("took "), "{}", time);
+
+    assert!(session.eval(":nope").is_err());
+}
+
+#[test]
+fn loads_files_into_the_session() {
+    let path = std::env::temp_dir().join(format!("blox-repl-{}.lox", std::process::id()));
+    fs::write(&path, "var loaded = 1 + 1;\n").unwrap();
+
+    let mut session = Session::new(DEFAULT_JIT_THRESHOLD);
+    let load = session.eval(&format!(":load {}", path.display()));
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(load, Ok(String::new()));
+    assert_eq!(session.eval(":globals"), Ok("loaded = 2".to_string()));
+    assert!(session.eval(":load /no/such/file.lox").is_err());
+}
+
+#[test]
+fn prints_the_value_of_bare_expressions() {
+    let mut session = Session::new(DEFAULT_JIT_THRESHOLD);
+    assert_eq!(session.eval("1 + 2"), Ok("3".to_string()));
+    assert_eq!(session.eval("1 + 2;"), Ok("3".to_string()));
+    assert_eq!(session.eval("\"a\" + \"b\""), Ok("ab".to_string()));
+    assert_eq!(session.eval("1 < 2"), Ok("true".to_string()));
+    assert_eq!(session.eval("nil"), Ok("nil".to_string()));
+    assert_eq!(session.eval("0.5 * 3"), Ok("1.5".to_string()));
+
+    assert_eq!(session.eval("class Point {}"), Ok(String::new()));
+    assert_eq!(session.eval("Point"), Ok("Point".to_string()));
+    assert_eq!(session.eval("Point()"), Ok("Point instance".to_string()));
+    assert_eq!(session.eval("fun f() {}"), Ok(String::new()));
+    assert_eq!(session.eval("f"), Ok("<fn f>".to_string()));
+    assert_eq!(session.eval("f()"), Ok("nil".to_string()));
+
+    // statements stay quiet, even ones ending in an expression
+    assert_eq!(session.eval("var a = 1;"), Ok(String::new()));
+    assert_eq!(session.eval("a = 2; a;"), Ok(String::new()));
+    assert_eq!(session.eval("a = 3"), Ok("3".to_string()));
+    assert!(session.eval("1 +").is_err());
+}
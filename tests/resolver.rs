@@ -0,0 +1,93 @@
+use blox::ast::{Local, Statement, Variable};
+use blox::parser::Parser;
+use blox::resolver::Resolver;
+use blox::visit::{walk_statements, Visitor};
+
+fn resolve(src: &str) -> Result<Vec<Statement>, Vec<String>> {
+    let mut program = Parser::new(src).parse_program().unwrap();
+    Resolver::default()
+        .resolve(&mut program)
+        .map(|()| program)
+        .map_err(|errors| errors.iter().map(|e| e.to_string()).collect())
+}
+
+#[derive(Default)]
+struct Uses(Vec<(String, Option<Local>)>);
+
+impl Visitor for Uses {
+    fn visit_variable(&mut self, variable: &Variable) {
+        self.0.push((variable.name.to_string(), variable.local));
+    }
+}
+
+// every use of a name in the program with where it resolved to
+fn uses(src: &str) -> Vec<(String, Option<Local>)> {
+    let program = resolve(src).unwrap();
+    let mut uses = Uses::default();
+    walk_statements(&mut uses, &program);
+    uses.0
+}
+
+fn local(name: &str, depth: usize, slot: usize) -> (String, Option<Local>) {
+    (name.to_string(), Some(Local { depth, slot }))
+}
+
+fn global(name: &str) -> (String, Option<Local>) {
+    (name.to_string(), None)
+}
+
+#[test]
+fn shadowing_resolves_to_the_innermost_declaration() {
+    assert_eq!(
+        uses("var a = 1; { var a = 2; var b = 3; { var b = 4; print a; print b; } print b; } print a;"),
+        vec![
+            local("a", 1, 0),
+            local("b", 0, 0),
+            local("b", 0, 1),
+            global("a"),
+        ]
+    );
+}
+
+#[test]
+fn closures_reach_out_to_enclosing_functions() {
+    assert_eq!(
+        uses("fun outer(x) { var y = 1; fun inner(z) { return x + y + z; } return inner; }"),
+        vec![
+            local("x", 1, 0),
+            local("y", 1, 1),
+            local("z", 0, 0),
+            local("inner", 0, 2),
+        ]
+    );
+}
+
+#[test]
+fn reading_a_local_in_its_own_initializer_is_an_error() {
+    assert_eq!(
+        resolve("{ var a = 1; { var a = a; } }").unwrap_err(),
+        vec!["[line 1] Error at 'a': Can't read local variable in its own initializer."]
+    );
+    // globals are late bound, so this only fails at runtime
+    assert!(resolve("var a = a;").is_ok());
+}
+
+#[test]
+fn misplaced_declarations_and_returns_are_errors() {
+    assert_eq!(
+        resolve("fun f() { var a; var a; }").unwrap_err(),
+        vec!["[line 1] Error at 'a': Already a variable with this name in this scope."]
+    );
+    assert_eq!(
+        resolve("return 1;").unwrap_err(),
+        vec!["[line 1] Error at 'return': Can't return from top-level code."]
+    );
+    assert_eq!(
+        resolve("class A { init() { return 1; } }").unwrap_err(),
+        vec!["[line 1] Error at 'return': Can't return a value from an initializer."]
+    );
+    assert_eq!(
+        resolve("print this;").unwrap_err(),
+        vec!["[line 1] Error at 'this': Can't use 'this' outside of a class."]
+    );
+}
@@ -0,0 +1,41 @@
+#![cfg(feature = "serde")]
+
+use blox::ast::Statement;
+use blox::lexer::{Lexer, Token};
+use blox::parser::Parser;
+use blox::resolver::Resolver;
+
+const SRC: &str = "fun f(a) { var b = a * 2; return b; } class C { init() { this.x = f(1); } }
+print \"a \\\"quoted\\\"\\tline\nand another\";";
+
+#[test]
+fn ast_round_trips_through_json() {
+    let mut program = Parser::new(SRC).parse_program().unwrap();
+    Resolver::default().resolve(&mut program).unwrap();
+
+    let json = serde_json::to_string(&program).unwrap();
+    let read: Vec<Statement> = serde_json::from_str(&json).unwrap();
+    assert_eq!(read, program);
+}
+
+#[test]
+fn tokens_are_written_with_their_raw_lexemes() {
+    let tokens: Vec<Token> = Lexer::new(SRC).collect();
+
+    let json = serde_json::to_value(&tokens).unwrap();
+    let written = json.as_array().unwrap();
+    assert_eq!(written.len(), tokens.len());
+    for (json, token) in written.iter().zip(&tokens) {
+        assert_eq!(json["lexeme"], token.lexeme);
+        assert_eq!(json["line"], token.line);
+        assert_eq!(json["column"], token.column);
+    }
+
+    // escapes are kept as they were written in the source
+    let string = tokens
+        .iter()
+        .find(|token| token.lexeme.contains("quoted"))
+        .unwrap();
+    assert_eq!(string.lexeme, "a \\\"quoted\\\"\\tline\nand another");
+    assert_eq!(string.value(), "a \"quoted\"\tline\nand another");
+}
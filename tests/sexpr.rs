@@ -0,0 +1,33 @@
+use blox::parser::Parser;
+use blox::sexpr::Sexpr;
+
+fn sexpr(src: &str) -> Vec<String> {
+    match Parser::new(src).parse_program() {
+        Ok(program) => program.iter().map(|s| Sexpr(s).to_string()).collect(),
+        Err(e) => panic!("failed to parse {:?}: {:?}", src, e),
+    }
+}
+
+#[test]
+fn expressions() {
+    assert_eq!(
+        sexpr("print -a.b(1, \"s\") * (2 + 3) == nil;"),
+        ["(print (== (* (- (call (get a b) 1 \"s\")) (group (+ 2 3))) nil))"]
+    );
+    assert_eq!(
+        sexpr("a = b.c = !true;"),
+        ["(expr (assign a (set b c (! true))))"]
+    );
+}
+
+#[test]
+fn statements() {
+    assert_eq!(
+        sexpr("for (var i = 0; i < 2; i = i + 1) print i;"),
+        ["(block (var i 0) (while (< i 2) (block (print i) (expr (assign i (+ i 1))))))"]
+    );
+    assert_eq!(
+        sexpr("class A { m(x) { if (x) return; else return this; } }"),
+        ["(class A (fun m (x) (if x (return) (return this))))"]
+    );
+}
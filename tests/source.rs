@@ -0,0 +1,55 @@
+use blox::source::{ColumnUnit, Position, SourceFile};
+
+fn position(line: u64, column: u64) -> Position {
+    Position { line, column }
+}
+
+// 🦀 is 4 bytes and 2 UTF-16 units, 日 and 本 are 3 bytes and 1 unit each
+const SRC: &str = "a🦀b\n日本 = 1;\n";
+
+#[test]
+fn columns_count_chars_or_utf16_units() {
+    let file = SourceFile::new("test.lox", SRC);
+    let b = SRC.find('b').unwrap();
+    assert_eq!(file.position(b, ColumnUnit::Char), position(1, 3));
+    assert_eq!(file.position(b, ColumnUnit::Utf16), position(1, 4));
+
+    let equals = SRC.find('=').unwrap();
+    assert_eq!(file.position(equals, ColumnUnit::Char), position(2, 4));
+    assert_eq!(file.position(equals, ColumnUnit::Utf16), position(2, 4));
+
+    for unit in [ColumnUnit::Char, ColumnUnit::Utf16] {
+        for (offset, _) in SRC.char_indices() {
+            let at = file.position(offset, unit);
+            assert_eq!(file.offset(at, unit), Some(offset), "{:?} {:?}", at, unit);
+        }
+    }
+}
+
+#[test]
+fn offsets_inside_a_char_round_down_to_its_start() {
+    let file = SourceFile::new("test.lox", SRC);
+    let crab = SRC.find('🦀').unwrap();
+    for offset in crab..crab + '🦀'.len_utf8() {
+        assert_eq!(file.position(offset, ColumnUnit::Char), position(1, 2));
+        assert_eq!(file.position(offset, ColumnUnit::Utf16), position(1, 2));
+    }
+    let hon = SRC.find('本').unwrap();
+    for offset in hon..hon + '本'.len_utf8() {
+        assert_eq!(file.position(offset, ColumnUnit::Utf16), position(2, 2));
+    }
+
+    // past the end is clamped to the end
+    assert_eq!(file.position(1000, ColumnUnit::Char), position(3, 1));
+}
+
+#[test]
+fn positions_inside_a_char_have_no_offset() {
+    let file = SourceFile::new("test.lox", SRC);
+    // the second half of the crab's surrogate pair
+    assert_eq!(file.offset(position(1, 3), ColumnUnit::Utf16), None);
+    assert_eq!(file.offset(position(1, 4), ColumnUnit::Utf16), Some(5));
+    assert_eq!(file.offset(position(1, 9), ColumnUnit::Char), None);
+    assert_eq!(file.offset(position(9, 1), ColumnUnit::Char), None);
+    assert_eq!(file.line_text(2), Some("日本 = 1;"));
+}
@@ -0,0 +1,38 @@
+use std::collections::HashMap;
+use std::thread;
+
+use blox::symbol::Symbol;
+
+#[test]
+fn symbols_compare_by_name() {
+    let a = Symbol::intern("name");
+    assert_eq!(a, Symbol::intern("name"));
+    assert_ne!(a, Symbol::intern("other"));
+    assert_eq!(a.as_str(), "name");
+    assert_eq!(a.to_string(), "name");
+    assert_eq!(format!("{:?}", a), "\"name\"");
+    assert!(Symbol::intern("a") < Symbol::intern("b"));
+}
+
+#[test]
+fn symbols_from_other_threads_are_the_same_symbols() {
+    let here = Symbol::intern("shared");
+    let there = thread::spawn(|| Symbol::intern("shared")).join().unwrap();
+    assert_eq!(here, there);
+    assert_eq!(here.as_str().as_ptr(), there.as_str().as_ptr());
+
+    let mut globals = HashMap::new();
+    globals.insert(there, 1);
+    assert_eq!(globals.get(&here), Some(&1));
+}
+
+#[test]
+fn names_can_be_interned_again_after_they_are_dropped() {
+    for round in 0..3 {
+        let names: Vec<Symbol> = (0..1000)
+            .map(|idx| Symbol::intern(&format!("name{}", idx)))
+            .collect();
+        assert_eq!(names[7].as_str(), "name7", "round {}", round);
+        assert_eq!(names[999], Symbol::intern("name999"));
+    }
+}
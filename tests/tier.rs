@@ -0,0 +1,235 @@
+use std::rc::Rc;
+
+use blox::interpreter::{Closure, Interpreter, RuntimeError, Value};
+use blox::parser::Parser;
+use blox::resolver::Resolver;
+use blox::tier::{Counters, Tier};
+
+fn run(interpreter: &mut Interpreter, src: &str) {
+    let mut program = Parser::new(src).parse_program().unwrap();
+    Resolver::default().resolve(&mut program).unwrap();
+    interpreter.execute(&program).unwrap();
+}
+
+fn global(interpreter: &Interpreter, name: &str) -> Value {
+    interpreter
+        .globals()
+        .find(|(global, _)| global.as_str() == name)
+        .map(|(_, value)| value.clone())
+        .unwrap_or_else(|| panic!("no global {}", name))
+}
+
+fn function(interpreter: &Interpreter, name: &str) -> Rc<Closure> {
+    match global(interpreter, name) {
+        Value::Function(function) => function,
+        value => panic!("{} is {:?}", name, value),
+    }
+}
+
+#[test]
+fn hot_functions_move_to_the_jit() {
+    let mut interpreter = Interpreter::new();
+    interpreter.set_jit_threshold(10);
+    run(
+        &mut interpreter,
+        "fun answer() { return 6 * 7; } var total = 0;",
+    );
+    let answer = function(&interpreter, "answer");
+
+    run(
+        &mut interpreter,
+        "for (var i = 0; i < 10; i = i + 1) total = total + answer();",
+    );
+    assert_eq!(answer.tier(), Tier::Interpreted);
+
+    // the call that crosses the threshold is the first to run compiled
+    run(&mut interpreter, "total = total + answer();");
+    assert_eq!(answer.tier(), Tier::Compiled);
+    run(
+        &mut interpreter,
+        "for (var i = 0; i < 9; i = i + 1) total = total + answer();",
+    );
+
+    assert_eq!(global(&interpreter, "total"), Value::Number(42.0 * 20.0));
+    assert_eq!(
+        answer.counters(),
+        Counters {
+            calls: 20,
+            backedges: 0
+        }
+    );
+}
+
+#[test]
+fn loops_count_towards_the_function_they_run_in() {
+    let mut interpreter = Interpreter::new();
+    interpreter.set_jit_threshold(1000);
+    run(
+        &mut interpreter,
+        "fun spin(n) { var i = 0; while (i < n) i = i + 1; return i; } spin(5); spin(3);",
+    );
+    let spin = function(&interpreter, "spin");
+    assert_eq!(
+        spin.counters(),
+        Counters {
+            calls: 2,
+            backedges: 8
+        }
+    );
+    assert_eq!(spin.tier(), Tier::Interpreted);
+}
+
+#[test]
+fn functions_the_jit_rejects_keep_running_interpreted() {
+    let mut interpreter = Interpreter::new();
+    interpreter.set_jit_threshold(0);
+    run(
+        &mut interpreter,
+        "fun greet() { return \"hi\"; } var s; for (var i = 0; i < 5; i = i + 1) s = greet();",
+    );
+    assert_eq!(function(&interpreter, "greet").tier(), Tier::Interpreted);
+    assert_eq!(global(&interpreter, "s"), Value::String("hi".to_string()));
+}
+
+#[test]
+fn compiled_functions_read_their_parameters() {
+    let mut interpreter = Interpreter::new();
+    interpreter.set_jit_threshold(0);
+    run(
+        &mut interpreter,
+        "fun area(w, h) { return w * h - w; } var a = area(3, 4); var b = area(5, 2);",
+    );
+    assert_eq!(function(&interpreter, "area").tier(), Tier::Compiled);
+    assert_eq!(global(&interpreter, "a"), Value::Number(9.0));
+    assert_eq!(global(&interpreter, "b"), Value::Number(5.0));
+}
+
+#[test]
+fn compiled_functions_keep_numeric_locals_in_registers() {
+    let mut interpreter = Interpreter::new();
+    interpreter.set_jit_threshold(0);
+    run(
+        &mut interpreter,
+        "fun norm(x, y) { var xx = x * x; var yy = y * y; xx = xx + yy; return xx - 1; } var n = norm(3, 4);",
+    );
+    assert_eq!(function(&interpreter, "norm").tier(), Tier::Compiled);
+    assert_eq!(global(&interpreter, "n"), Value::Number(24.0));
+}
+
+#[test]
+fn arguments_the_code_was_not_specialized_for_run_interpreted() {
+    let mut interpreter = Interpreter::new();
+    interpreter.set_jit_threshold(0);
+    run(
+        &mut interpreter,
+        "fun add(a, b) { var sum = a + b; return sum; } var n = add(1, 2);",
+    );
+    let add = function(&interpreter, "add");
+    assert_eq!(add.tier(), Tier::Compiled);
+
+    // strings add too, just not in the compiled code
+    run(&mut interpreter, "var s = add(\"a\", \"b\");");
+    assert_eq!(global(&interpreter, "s"), Value::String("ab".to_string()));
+
+    let mut program = Parser::new("add(1, nil);").parse_program().unwrap();
+    Resolver::default().resolve(&mut program).unwrap();
+    assert_eq!(
+        interpreter.execute(&program).unwrap_err().to_string(),
+        "Operands must be two numbers or two strings."
+    );
+    assert_eq!(add.tier(), Tier::Compiled);
+    assert_eq!(global(&interpreter, "n"), Value::Number(3.0));
+}
+
+#[test]
+fn locals_that_change_type_keep_the_function_interpreted() {
+    let mut interpreter = Interpreter::new();
+    interpreter.set_jit_threshold(0);
+    run(
+        &mut interpreter,
+        "fun f(a) { var x = a; x = \"s\"; return a; } var n = f(1); n = f(2);",
+    );
+    assert_eq!(function(&interpreter, "f").tier(), Tier::Interpreted);
+    assert_eq!(global(&interpreter, "n"), Value::Number(2.0));
+}
+
+#[test]
+fn closures_share_the_tier_of_their_declaration() {
+    let mut interpreter = Interpreter::new();
+    interpreter.set_jit_threshold(10);
+    // every iteration makes a new closure from the same declaration
+    run(
+        &mut interpreter,
+        "var last; var sum = 0;
+         for (var i = 0; i < 20; i = i + 1) {
+             fun square(x) { return x * x; }
+             sum = sum + square(i);
+             last = square;
+         }",
+    );
+    let square = function(&interpreter, "last");
+    assert_eq!(square.tier(), Tier::Compiled);
+    assert_eq!(
+        square.counters(),
+        Counters {
+            calls: 20,
+            backedges: 0
+        }
+    );
+    assert_eq!(global(&interpreter, "sum"), Value::Number(2470.0));
+}
+
+#[test]
+fn methods_reading_fields_are_compiled_behind_shape_guards() {
+    let mut interpreter = Interpreter::new();
+    interpreter.set_jit_threshold(10);
+    run(
+        &mut interpreter,
+        "class Rect { area() { return this.w * this.h; } }
+         var r = Rect(); r.w = 3; r.h = 4; var total = 0;
+         for (var i = 0; i < 20; i = i + 1) total = total + r.area();
+         var area = r.area;",
+    );
+    let area = function(&interpreter, "area");
+    assert_eq!(area.tier(), Tier::Compiled);
+    assert_eq!(global(&interpreter, "total"), Value::Number(240.0));
+    // compiled reads count as hits too
+    assert_eq!(interpreter.cache_stats().hits, 38 + 19);
+
+    // the fields in another order make a shape the guards don't know, so
+    // the call bails out to the interpreter and the next one recompiles
+    run(
+        &mut interpreter,
+        "var s = Rect(); s.h = 2; s.w = 5; var a = s.area();",
+    );
+    assert_eq!(global(&interpreter, "a"), Value::Number(10.0));
+    assert_eq!(area.tier(), Tier::Interpreted);
+    run(&mut interpreter, "var b = s.area(); var c = r.area();");
+    assert_eq!(area.tier(), Tier::Compiled);
+    assert_eq!(global(&interpreter, "b"), Value::Number(10.0));
+    assert_eq!(global(&interpreter, "c"), Value::Number(12.0));
+}
+
+#[test]
+fn fields_that_are_not_numbers_run_interpreted() {
+    let mut interpreter = Interpreter::new();
+    interpreter.set_jit_threshold(0);
+    run(
+        &mut interpreter,
+        "class P { next() { return this.x + 1; } }
+         var p = P(); p.x = 1; var a = p.next(); var b = p.next(); var next = p.next;",
+    );
+    assert_eq!(global(&interpreter, "b"), Value::Number(2.0));
+    let next = function(&interpreter, "next");
+    assert_eq!(next.tier(), Tier::Compiled);
+
+    let mut program = Parser::new("p.x = \"one\"; p.next();")
+        .parse_program()
+        .unwrap();
+    Resolver::default().resolve(&mut program).unwrap();
+    assert_eq!(
+        interpreter.execute(&program),
+        Err(RuntimeError::OperandsMustBeNumbersOrStrings)
+    );
+    assert_eq!(next.tier(), Tier::Interpreted);
+}
@@ -0,0 +1,66 @@
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+
+// Golden tests for `blox tokens`: the dump of every script under
+// tests/tokens has to match the .tokens file next to it. Run with
+// BLESS=1 to write the current output as the expected one.
+
+const GOLDEN: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/tokens");
+
+fn blox(args: &[&str], dir: &Path) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_blox"))
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn token_dumps_match_the_golden_files() {
+    let mut scripts: Vec<_> = fs::read_dir(GOLDEN)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "lox"))
+        .collect();
+    scripts.sort();
+    assert!(!scripts.is_empty());
+
+    for script in scripts {
+        let output = blox(&["tokens", script.to_str().unwrap()], Path::new(GOLDEN));
+        assert!(output.status.success(), "{}", script.display());
+        let actual = String::from_utf8(output.stdout).unwrap();
+
+        let golden = script.with_extension("tokens");
+        if std::env::var_os("BLESS").is_some() {
+            fs::write(&golden, &actual).unwrap();
+            continue;
+        }
+        let expected = fs::read_to_string(&golden).unwrap();
+        assert_eq!(actual, expected, "{}", script.display());
+    }
+}
+
+#[test]
+fn scripts_named_like_subcommands_run_after_dashes() {
+    let dir = std::env::temp_dir().join(format!("blox-tokens-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("tokens"), "print \"ran\";\n").unwrap();
+
+    let run = blox(&["--", "tokens"], &dir);
+    let dump = blox(&["tokens", "tokens"], &dir);
+    let extra = blox(&["--", "tokens", "tokens"], &dir);
+    // without a path they are usage errors rather than scripts to run
+    let bare: Vec<_> = ["tokens", "fmt"]
+        .iter()
+        .map(|command| blox(&[command], &dir).status.code())
+        .collect();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(String::from_utf8(run.stdout).unwrap(), "ran\n");
+    assert!(String::from_utf8(dump.stdout)
+        .unwrap()
+        .starts_with("1:1 Print \"print\" 0..5\n"));
+    assert_eq!(extra.status.code(), Some(64));
+    assert_eq!(bare, [Some(64), Some(64)]);
+}
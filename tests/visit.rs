@@ -0,0 +1,85 @@
+use blox::ast::{Expression, Statement, Variable};
+use blox::parser::Parser;
+use blox::symbol::Symbol;
+use blox::visit::{walk_expression_mut, walk_statement, Visitor, VisitorMut};
+
+fn parse(src: &str) -> Vec<Statement> {
+    match Parser::new(src).parse_program() {
+        Ok(program) => program,
+        Err(e) => panic!("failed to parse {:?}: {:?}", src, e),
+    }
+}
+
+#[derive(Default)]
+struct Names {
+    declared: Vec<String>,
+    used: Vec<String>,
+    loops: usize,
+}
+
+impl Visitor for Names {
+    fn visit_statement(&mut self, statement: &Statement) {
+        if let Statement::While(..) = statement {
+            self.loops += 1;
+        }
+        walk_statement(self, statement);
+    }
+
+    fn visit_declaration(&mut self, variable: &Variable) {
+        self.declared.push(variable.name.to_string());
+    }
+
+    fn visit_variable(&mut self, variable: &Variable) {
+        self.used.push(variable.name.to_string());
+    }
+}
+
+#[test]
+fn visits_declarations_and_uses_in_order() {
+    let program = parse(
+        "var a = 1;
+        fun f(b) { while (b) { b = b - a; } return b; }
+        class C { m() { return this; } }",
+    );
+
+    let mut names = Names::default();
+    for statement in &program {
+        names.visit_statement(statement);
+    }
+
+    assert_eq!(names.declared, ["a", "f", "b", "C", "m"]);
+    assert_eq!(names.used, ["b", "b", "b", "a", "b", "this"]);
+    assert_eq!(names.loops, 1);
+}
+
+// replaces every use of one global with another, and turns `-literal` into
+// a negative literal
+struct Rewrite;
+
+impl VisitorMut for Rewrite {
+    fn visit_expression(&mut self, expression: &mut Expression) {
+        walk_expression_mut(self, expression);
+        if let Expression::Unary(_, operand) = expression {
+            if let Expression::Number(num) = **operand {
+                *expression = Expression::Number(-num);
+            }
+        }
+    }
+
+    fn visit_variable(&mut self, variable: &mut Variable) {
+        if variable.name.as_str() == "old" {
+            variable.name = Symbol::intern("new");
+        }
+    }
+}
+
+#[test]
+fn rewrites_in_place() {
+    let mut program = parse("print old + -2; old = old * 3;");
+    for statement in &mut program {
+        Rewrite.visit_statement(statement);
+    }
+
+    let printed: Vec<String> = program.iter().map(|s| s.to_string()).collect();
+    assert_eq!(printed, ["print new + -2;", "new = new * 3;"]);
+}